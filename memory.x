@@ -1,7 +1,8 @@
 /* RP2040 メモリレイアウト（rp-hal-boards準拠） */
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* 末尾 4 KiB は累計値の保存用（src/persist.rs）に空けておく */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 4K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -0,0 +1,73 @@
+//! 固定長バッファへの ASCII 書き込み（CSV/JSON/USB シリアルの行で共用）
+//! - 溢れた分は黙って切り捨てる（呼び出し側が最大長以上のバッファを渡す）
+//! - 整数は10進のみ（浮動小数の書式化は `fmt` で文字列にしてから渡す）
+//! - 非 ASCII 文字は `?` に置き換える
+
+/// 溢れを黙って切り捨てる書き込み先
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// `buf` の先頭から書く
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// 1バイト追加
+    pub fn push(&mut self, b: u8) {
+        if self.len < self.buf.len() {
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    /// 文字列を追加（非 ASCII 文字は `?`）
+    pub fn push_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.push(if c.is_ascii() { c as u8 } else { b'?' });
+        }
+    }
+
+    /// 符号なし整数を10進で追加
+    pub fn push_u64(&mut self, mut x: u64) {
+        let mut digits = [0u8; 20];
+        let mut n = 0;
+        loop {
+            digits[n] = b'0' + (x % 10) as u8;
+            n += 1;
+            x /= 10;
+            if x == 0 {
+                break;
+            }
+        }
+        while n > 0 {
+            n -= 1;
+            self.push(digits[n]);
+        }
+    }
+
+    /// 符号付き整数を10進で追加（負なら `-` を前置）
+    pub fn push_i64(&mut self, x: i64) {
+        if x < 0 {
+            self.push(b'-');
+        }
+        self.push_u64(x.unsigned_abs());
+    }
+
+    /// 書いたバイト数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// まだ何も書いていないか
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 書き込んだ部分
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
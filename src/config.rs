@@ -0,0 +1,108 @@
+//! ビルド時の設定の読み取り（`option_env!` の文字列を const 文脈で数値にする）
+//! - `main.rs` の設定定数は `env_u32!("NAME", 既定値)` の形で書く
+//! - 値が不正（空、数字以外、小数、u32 超過）ならコンパイルエラーにする。シャント抵抗などの打ち間違いを
+//!   既定値で黙って置き換えると、積算値が気づかないまま狂うため
+
+/// ビルド時の環境変数 `$name` を u32 として読む。未設定なら `$default`
+/// 10進整数、`0x` 付きなら16進。不正な値は変数名つきのコンパイルエラー
+/// 例: `const SHUNT_UOHM: u32 = env_u32!("SHUNT_UOHM", 100_000);`
+#[macro_export]
+macro_rules! env_u32 {
+    ($name:literal, $default:expr) => {
+        match $crate::config::value_or(option_env!($name), $default) {
+            Some(v) => v,
+            None => core::panic!(concat!(
+                "環境変数 ",
+                $name,
+                " は u32 の整数（10進、または 0x 付きの16進）で指定すること"
+            )),
+        }
+    };
+}
+
+/// 環境変数の値 `v` を読む。未設定なら `Some(default)`、不正なら `None`（`env_u32!` 用）
+pub const fn value_or(v: Option<&str>, default: u32) -> Option<u32> {
+    match v {
+        Some(s) => parse_u32(s),
+        None => Some(default),
+    }
+}
+
+/// 10進整数（`0x` / `0X` 付きなら16進）を読む。空・数字以外・u32 超過なら `None`
+pub const fn parse_u32(s: &str) -> Option<u32> {
+    let bytes = s.as_bytes();
+    // `0x` / `0X` で始まれば16進（I2C アドレス用）
+    let (radix, mut i) = match bytes {
+        [b'0', b'x' | b'X', ..] => (16, 2),
+        _ => (10, 0),
+    };
+    if bytes.len() <= i {
+        return None;
+    }
+    let mut n: u64 = 0;
+    while i < bytes.len() {
+        let d = match bytes[i] {
+            b @ b'0'..=b'9' => b - b'0',
+            b @ b'a'..=b'f' if radix == 16 => b - b'a' + 10,
+            b @ b'A'..=b'F' if radix == 16 => b - b'A' + 10,
+            _ => return None,
+        };
+        n = n * radix + d as u64;
+        if n > u32::MAX as u64 {
+            return None;
+        }
+        i += 1;
+    }
+    Some(n as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_u32_reads_decimal_and_hex() {
+        assert_eq!(parse_u32("2000"), Some(2000));
+        assert_eq!(parse_u32("0"), Some(0));
+        assert_eq!(parse_u32("0x44"), Some(0x44));
+        assert_eq!(parse_u32("0X4f"), Some(0x4F));
+        assert_eq!(parse_u32("4294967295"), Some(u32::MAX));
+    }
+
+    #[test]
+    fn parse_u32_rejects_malformed_values() {
+        for bad in [
+            "",
+            "0x",
+            "-1",
+            "+1",
+            "12a",
+            "0.002",
+            "400k",
+            " 100",
+            "100 ",
+            "1_000",
+            "0xG",
+            "4294967296",
+            "0x100000000",
+        ] {
+            assert_eq!(parse_u32(bad), None, "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn value_or_uses_default_only_when_unset() {
+        assert_eq!(value_or(None, 7), Some(7));
+        assert_eq!(value_or(Some("500"), 7), Some(500));
+        // 空や不正な値は既定値に置き換えない
+        assert_eq!(value_or(Some(""), 7), None);
+        assert_eq!(value_or(Some("0.002"), 7), None);
+    }
+
+    #[test]
+    fn env_u32_macro_is_usable_in_const() {
+        // テスト用に設定されることのない名前なので既定値
+        const X: u32 = env_u32!("PICO_VA_MONITOR_UNSET_FOR_TEST", 500);
+        assert_eq!(X, 500);
+    }
+}
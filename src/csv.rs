@@ -0,0 +1,318 @@
+//! CSV 出力（feature `csv`）
+//! - 起動時に `HEADER` を1回、以降はサンプルごとに1行
+//! - 整数のみで整形（浮動小数の書式化を避け、内部の固定小数点表現をそのまま出す）
+//! - 集計窓ごとの行（feature `minute-crc`）は `M,…*XXXX` の形で、`*` の前までの CRC16 を16進4桁で付ける
+//! - 固定レートの行（feature `csv-fixed-rate`）は計測周期ごとに必ず1行。新データがなければ直前の値を `fresh=0` で繰り返す
+
+use crate::ascii::Writer;
+
+/// 見出し行（列の並びは `format_row` と一致させる）
+pub const HEADER: &str = "rail,elapsed_ms,v_mv,shunt_uv,i_ua,p_uw,energy_uws";
+
+/// 1行の最大長（各列の最大桁 + 区切り）
+pub const ROW_MAX: usize = 96;
+
+/// 1行を `buf` に書き込み、書いたバイト数を返す（改行なし）
+/// バッファが足りない分は切り捨てる（`ROW_MAX` 以上を渡せば切れない）
+/// `i_ua` / `p_uw` が `None`（未校正）の列は空にする
+#[allow(clippy::too_many_arguments)]
+pub fn format_row(
+    rail: &str,
+    elapsed_ms: u64,
+    v_mv: i32,
+    shunt_uv: i32,
+    i_ua: Option<i32>,
+    p_uw: Option<i32>,
+    energy_uws: i64,
+    buf: &mut [u8],
+) -> usize {
+    let mut w = Writer::new(buf);
+    w.push_str(rail);
+    w.push(b',');
+    w.push_u64(elapsed_ms);
+    w.push(b',');
+    push_sample(
+        &mut w,
+        &Sample {
+            v_mv,
+            shunt_uv,
+            i_ua,
+            p_uw,
+        },
+    );
+    w.push(b',');
+    w.push_i64(energy_uws);
+    w.len()
+}
+
+/// 固定レートの行の見出し（列の並びは `format_fixed_row` と一致させる）
+pub const FIXED_HEADER: &str = "rail,elapsed_ms,fresh,v_mv,shunt_uv,i_ua,p_uw,energy_uws";
+
+/// 1サンプル分の計測値（固定レートの行で直前の値を繰り返すために保持する）
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sample {
+    pub v_mv: i32,
+    pub shunt_uv: i32,
+    /// 未校正なら `None`（列は空）
+    pub i_ua: Option<i32>,
+    pub p_uw: Option<i32>,
+}
+
+/// 直前の値の保持：新データがあればそれを、なければ直前の値を返す（どちらかは `fresh` で区別）
+#[derive(Clone, Copy, Default)]
+pub struct HoldLast<T> {
+    last: Option<T>,
+}
+
+impl<T: Copy> HoldLast<T> {
+    /// 新規作成（まだ値なし）
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// 今周の新データ `fresh`（なければ `None`）を入れ、(出す値, 新データか) を返す
+    /// 一度も値が来ていなければ `None`（最初のサンプルより前の行は出さない）
+    pub fn next(&mut self, fresh: Option<T>) -> Option<(T, bool)> {
+        match fresh {
+            Some(x) => {
+                self.last = Some(x);
+                Some((x, true))
+            }
+            None => self.last.map(|x| (x, false)),
+        }
+    }
+
+    /// 保持している値を捨てる
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// 固定レートの1行を `buf` に書き込み、書いたバイト数を返す（改行なし）
+/// `fresh` は今周に読めた値なら 1、読めず直前の値を繰り返したなら 0
+pub fn format_fixed_row(
+    rail: &str,
+    elapsed_ms: u64,
+    fresh: bool,
+    sample: &Sample,
+    energy_uws: i64,
+    buf: &mut [u8],
+) -> usize {
+    let mut w = Writer::new(buf);
+    w.push_str(rail);
+    w.push(b',');
+    w.push_u64(elapsed_ms);
+    w.push(b',');
+    w.push(if fresh { b'1' } else { b'0' });
+    w.push(b',');
+    push_sample(&mut w, sample);
+    w.push(b',');
+    w.push_i64(energy_uws);
+    w.len()
+}
+
+/// 集計窓の行の最大長
+pub const MINUTE_ROW_MAX: usize = 96;
+
+/// 集計窓の結果を `M,index,avg_v_mv,avg_i_ua,energy_uwms,covered_ms*XXXX` の形で `buf` に書き、書いたバイト数を返す（改行なし）
+/// `XXXX` は先頭 `M` から `*` の直前までの CRC-16/CCITT-FALSE（大文字16進）
+pub fn format_minute_row(
+    index: u64,
+    avg_v_mv: i32,
+    avg_i_ua: i32,
+    energy_uwms: i64,
+    covered_ms: u64,
+    buf: &mut [u8],
+) -> usize {
+    let mut w = Writer::new(buf);
+    w.push(b'M');
+    w.push(b',');
+    w.push_u64(index);
+    w.push(b',');
+    w.push_i64(avg_v_mv as i64);
+    w.push(b',');
+    w.push_i64(avg_i_ua as i64);
+    w.push(b',');
+    w.push_i64(energy_uwms);
+    w.push(b',');
+    w.push_u64(covered_ms);
+    let crc = crc16(w.as_bytes());
+    w.push(b'*');
+    for shift in [12, 8, 4, 0] {
+        w.push(b"0123456789ABCDEF"[((crc >> shift) & 0xF) as usize]);
+    }
+    w.len()
+}
+
+/// `format_minute_row` の行（改行なし）の CRC を検査する。`*XXXX` がない/合わなければ false
+pub fn verify_minute_row(line: &[u8]) -> bool {
+    let Some(star) = line.iter().rposition(|&b| b == b'*') else {
+        return false;
+    };
+    let hex = &line[star + 1..];
+    if hex.len() != 4 {
+        return false;
+    }
+    let mut crc: u16 = 0;
+    for &b in hex {
+        let d = match b {
+            b'0'..=b'9' => b - b'0',
+            b'A'..=b'F' => b - b'A' + 10,
+            _ => return false,
+        };
+        crc = (crc << 4) | d as u16;
+    }
+    crc == crc16(&line[..star])
+}
+
+/// CRC-16/CCITT-FALSE（多項式 0x1021、初期値 0xFFFF、反転なし）。"123456789" → 0x29B1
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// `v_mv,shunt_uv,i_ua,p_uw`（`None` の列は空）
+fn push_sample(w: &mut Writer, s: &Sample) {
+    w.push_i64(s.v_mv as i64);
+    w.push(b',');
+    w.push_i64(s.shunt_uv as i64);
+    w.push(b',');
+    if let Some(i_ua) = s.i_ua {
+        w.push_i64(i_ua as i64);
+    }
+    w.push(b',');
+    if let Some(p_uw) = s.p_uw {
+        w.push_i64(p_uw as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_row_writes_header_order() {
+        let mut buf = [0u8; ROW_MAX];
+        let n = format_row(
+            "R0",
+            1_500,
+            5_020,
+            -1_230,
+            Some(-12_300),
+            Some(61_746),
+            9_000_000_000,
+            &mut buf,
+        );
+        assert_eq!(&buf[..n], b"R0,1500,5020,-1230,-12300,61746,9000000000");
+        assert_eq!(HEADER.split(',').count(), 7);
+    }
+
+    #[test]
+    fn format_row_leaves_uncalibrated_columns_empty_and_truncates() {
+        let mut buf = [0u8; ROW_MAX];
+        let n = format_row("R1", 0, 0, 10, None, None, 0, &mut buf);
+        assert_eq!(&buf[..n], b"R1,0,0,10,,,0");
+        // 最大桁でも ROW_MAX に収まる
+        let n = format_row(
+            "R2",
+            u64::MAX,
+            i32::MIN,
+            i32::MIN,
+            Some(i32::MIN),
+            Some(i32::MIN),
+            i64::MIN,
+            &mut buf,
+        );
+        assert!(n < ROW_MAX);
+        let mut short = [0u8; 6];
+        assert_eq!(format_row("R0", 1_500, 1, 2, None, None, 3, &mut short), 6);
+        assert_eq!(&short, b"R0,150");
+    }
+
+    #[test]
+    fn crc16_matches_ccitt_false_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), 0xFFFF);
+    }
+
+    #[test]
+    fn minute_row_round_trip_and_corruption() {
+        let mut buf = [0u8; MINUTE_ROW_MAX];
+        let n = format_minute_row(3, 5_020, -12_300, 7_000_000_000, 60_000, &mut buf);
+        let line = &buf[..n];
+        let star = line.iter().position(|&b| b == b'*').unwrap();
+        assert_eq!(&line[..star], b"M,3,5020,-12300,7000000000,60000");
+        let mut hex = [0u8; 4];
+        for (k, shift) in [12, 8, 4, 0].into_iter().enumerate() {
+            hex[k] = b"0123456789ABCDEF"[((crc16(&line[..star]) >> shift) & 0xF) as usize];
+        }
+        assert_eq!(&line[star + 1..], &hex);
+        assert!(verify_minute_row(line));
+
+        let mut bad = [0u8; MINUTE_ROW_MAX];
+        bad[..n].copy_from_slice(line);
+        bad[4] = b'9'; // 5020 → 5920
+        assert!(!verify_minute_row(&bad[..n]));
+        assert!(!verify_minute_row(&line[..n - 1]));
+        assert!(!verify_minute_row(&line[..star]));
+        // 最大桁でも MINUTE_ROW_MAX に収まる
+        assert!(
+            format_minute_row(u64::MAX, i32::MIN, i32::MIN, i64::MIN, u64::MAX, &mut buf)
+                < MINUTE_ROW_MAX
+        );
+    }
+
+    #[test]
+    fn fixed_rate_rows_flag_held_values_over_gapped_sequence() {
+        let s = |v_mv| Sample {
+            v_mv,
+            shunt_uv: 100,
+            i_ua: Some(1_000),
+            p_uw: Some(v_mv),
+        };
+        // 周ごとの読み出し結果（None は読めなかった周）
+        let reads = [None, Some(s(5_000)), None, None, Some(s(5_100)), None];
+        let mut hold = HoldLast::new();
+        let mut rows = [[0u8; ROW_MAX]; 6];
+        let mut lens = [0usize; 6];
+        let mut emitted = 0;
+        for (k, fresh) in reads.into_iter().enumerate() {
+            if let Some((sample, is_fresh)) = hold.next(fresh) {
+                lens[emitted] = format_fixed_row(
+                    "R0",
+                    k as u64 * 500,
+                    is_fresh,
+                    &sample,
+                    0,
+                    &mut rows[emitted],
+                );
+                emitted += 1;
+            }
+        }
+        // 最初のサンプルより前の周は出さず、以降は毎周1行
+        let expected: [&[u8]; 5] = [
+            b"R0,500,1,5000,100,1000,5000,0",
+            b"R0,1000,0,5000,100,1000,5000,0",
+            b"R0,1500,0,5000,100,1000,5000,0",
+            b"R0,2000,1,5100,100,1000,5100,0",
+            b"R0,2500,0,5100,100,1000,5100,0",
+        ];
+        assert_eq!(emitted, expected.len());
+        for (k, e) in expected.iter().enumerate() {
+            assert_eq!(&rows[k][..lens[k]], *e);
+        }
+        assert_eq!(FIXED_HEADER.split(',').count(), 8);
+        hold.reset();
+        assert_eq!(hold.next(None), None);
+    }
+}
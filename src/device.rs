@@ -0,0 +1,208 @@
+//! INA219 とのやりとりのうち HAL に依存しない部分（再試行の回し方、設定の読み戻しの比較）
+//! - I2C の転送や待ちは呼び出し側のクロージャ（`main.rs` がタイマ/ドライバをつなぐ）
+
+use ina219::configuration::Configuration;
+
+/// `op` を最大 `attempts` 回試す。失敗の間には `wait(n)`（n = 0 始まりの再試行番号）を呼ぶ
+/// 戻り値は最後の結果と、再試行した回数（初回成功なら 0）。`attempts` が 0 でも1回は試す
+pub fn with_retry<T, E>(
+    attempts: u32,
+    mut wait: impl FnMut(u32),
+    mut op: impl FnMut() -> Result<T, E>,
+) -> (Result<T, E>, u32) {
+    let mut n = 0;
+    loop {
+        let r = op();
+        if r.is_ok() || n + 1 >= attempts {
+            return (r, n);
+        }
+        wait(n);
+        n += 1;
+    }
+}
+
+/// 書いた設定と読み戻した設定を項目ごとに比べ、最初に食い違った項目名を返す
+/// リセットビットは書き込み後に自動で 0 に戻るので比べない
+pub fn config_mismatch(want: &Configuration, got: &Configuration) -> Option<&'static str> {
+    if want.bus_voltage_range != got.bus_voltage_range {
+        return Some("bus_voltage_range");
+    }
+    if want.shunt_voltage_range != got.shunt_voltage_range {
+        return Some("shunt_voltage_range");
+    }
+    if want.bus_resolution != got.bus_resolution {
+        return Some("bus_resolution");
+    }
+    if want.shunt_resolution != got.shunt_resolution {
+        return Some("shunt_resolution");
+    }
+    if want.operating_mode != got.operating_mode {
+        return Some("operating_mode");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ina219::configuration::{
+        BusVoltageRange, OperatingMode, Reset, Resolution, ShuntVoltageRange,
+    };
+
+    #[test]
+    fn with_retry_stops_at_first_success() {
+        let mut calls = 0;
+        let mut waits = Vec::new();
+        let (r, retries) = with_retry(
+            3,
+            |n| waits.push(n),
+            || {
+                calls += 1;
+                if calls < 2 {
+                    Err(())
+                } else {
+                    Ok(calls)
+                }
+            },
+        );
+        assert_eq!((r, retries), (Ok(2), 1));
+        assert_eq!(waits, [0]);
+    }
+
+    #[test]
+    fn with_retry_gives_up_after_attempts_and_returns_last_error() {
+        let mut calls = 0;
+        let mut waits = Vec::new();
+        let (r, retries) = with_retry(
+            3,
+            |n| waits.push(n),
+            || -> Result<(), u32> {
+                calls += 1;
+                Err(calls)
+            },
+        );
+        assert_eq!((r, retries), (Err(3), 2));
+        assert_eq!(waits, [0, 1]);
+    }
+
+    #[test]
+    fn with_retry_tries_once_with_zero_attempts() {
+        let mut calls = 0;
+        let (r, retries) = with_retry(
+            0,
+            |_| panic!("no wait"),
+            || -> Result<(), ()> {
+                calls += 1;
+                Err(())
+            },
+        );
+        assert_eq!((r, retries, calls), (Err(()), 0, 1));
+    }
+
+    #[test]
+    fn config_mismatch_names_first_differing_field_and_ignores_reset() {
+        let want = Configuration {
+            bus_resolution: Resolution::Avg16,
+            ..Default::default()
+        };
+        assert_eq!(config_mismatch(&want, &want), None);
+        assert_eq!(
+            config_mismatch(
+                &want,
+                &Configuration {
+                    reset: Reset::Reset,
+                    ..want
+                }
+            ),
+            None
+        );
+        assert_eq!(
+            config_mismatch(&want, &Configuration::default()),
+            Some("bus_resolution")
+        );
+        let got = Configuration {
+            bus_voltage_range: BusVoltageRange::Fsr16v,
+            operating_mode: OperatingMode::PowerDown,
+            ..want
+        };
+        assert_eq!(config_mismatch(&want, &got), Some("bus_voltage_range"));
+        assert_eq!(
+            config_mismatch(
+                &want,
+                &Configuration {
+                    operating_mode: OperatingMode::PowerDown,
+                    ..want
+                }
+            ),
+            Some("operating_mode")
+        );
+    }
+
+    #[test]
+    fn with_retry_backoff_accounting_over_simulated_failures() {
+        // 2 回失敗してから成功する読み出し。待ちは 2 ms から倍々（main.rs の INA_RETRY_BASE_MS << n と同じ）
+        let mut failures_left = 2;
+        let mut waited_ms = 0;
+        let (r, retries) = with_retry(
+            3,
+            |n| waited_ms += 2u32 << n,
+            || {
+                if failures_left > 0 {
+                    failures_left -= 1;
+                    Err("nack")
+                } else {
+                    Ok(42)
+                }
+            },
+        );
+        assert_eq!(r, Ok(42));
+        assert_eq!(retries, 2);
+        assert_eq!(waited_ms, 2 + 4);
+    }
+
+    #[test]
+    fn config_mismatch_reports_each_field() {
+        let want = Configuration::default();
+        let cases = [
+            (
+                Configuration {
+                    bus_voltage_range: BusVoltageRange::Fsr16v,
+                    ..want
+                },
+                "bus_voltage_range",
+            ),
+            (
+                Configuration {
+                    shunt_voltage_range: ShuntVoltageRange::Fsr40mv,
+                    ..want
+                },
+                "shunt_voltage_range",
+            ),
+            (
+                Configuration {
+                    bus_resolution: Resolution::Res9Bit,
+                    ..want
+                },
+                "bus_resolution",
+            ),
+            (
+                Configuration {
+                    shunt_resolution: Resolution::Res9Bit,
+                    ..want
+                },
+                "shunt_resolution",
+            ),
+            (
+                Configuration {
+                    operating_mode: OperatingMode::AdcOff,
+                    ..want
+                },
+                "operating_mode",
+            ),
+        ];
+        for (got, field) in cases {
+            assert_eq!(config_mismatch(&want, &got), Some(field));
+            assert_eq!(config_mismatch(&got, &want), Some(field));
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! 固定幅の数値整形（defmt は幅/精度指定ができないため、ゼロ埋め文字列を作って `{=str}` で出す）
+//! - 入力は「値 × 10^frac_digits」の整数（例: 12.345 V → 12345 mV を frac_digits=3 で）
+//!   浮動小数からは `round_scaled` で四捨五入して作る（`as u32` の切り捨てだと 0.29 が 0.28 になる）
+//! - 整数部は最低 1 桁、整数部/小数部とも最大 19 桁（それ以上は 19 桁に丸める）
+//! - 整数部が int_digits 桁に入らないときは全桁 9 に飽和（"99.999" など）
+//! - 桁数は `int_digits_for(最大値)` で期待する最大値から決められる（大電流のシャントでも頭打ちにしない）
+//! - 小数点はビルド時の環境変数 DECIMAL_SEP（1 文字、既定 '.'）。例: `DECIMAL_SEP=, cargo build --release`
+
+/// 小数点の文字（DECIMAL_SEP が 1 バイトの ASCII でなければ '.'）
+pub const DECIMAL_SEP: u8 = match option_env!("DECIMAL_SEP") {
+    Some(s) if s.len() == 1 && s.as_bytes()[0].is_ascii() => s.as_bytes()[0],
+    _ => b'.',
+};
+
+/// 桁数指定の上限（10^19 が u64 に収まる最大）
+pub const MAX_DIGITS: u8 = 19;
+
+/// `x` を 10^frac_digits 倍して四捨五入した整数（`fmt_fixed` の入力用）。負値/NaN は 0、u32 を超えたら u32::MAX
+/// 例: `round_scaled(0.29, 2)` → 29
+pub fn round_scaled(x: f32, frac_digits: u8) -> u32 {
+    let scale = libm::powf(10.0, frac_digits.min(MAX_DIGITS) as f32);
+    libm::roundf(x * scale) as u32 // as は飽和（負値/NaN → 0）
+}
+
+/// `value_scaled` を整数部 `int_digits` 桁・小数部 `frac_digits` 桁のゼロ埋め固定小数にして `buf` に書く
+/// 例: `fmt_fixed(3300, 2, 3, &mut buf)` → `"03.300"`、`frac_digits == 0` なら小数点なし
+/// `int_digits` は 1..=19、`frac_digits` は 0..=19 に丸める（`fmt_fixed(5, 0, 0, ..)` は `"5"`）
+/// バッファが足りない分は切り捨てる（`int_digits + frac_digits + 1` バイトあれば切れない）
+/// 小数点は `DECIMAL_SEP`
+pub fn fmt_fixed(value_scaled: u32, int_digits: u8, frac_digits: u8, buf: &mut [u8]) -> &str {
+    fmt_fixed_sep(value_scaled, int_digits, frac_digits, DECIMAL_SEP, buf)
+}
+
+/// `fmt_fixed` の小数点を `sep` で指定する版（例: `fmt_fixed_sep(3300, 2, 3, b',', &mut buf)` → `"03,300"`）
+/// `sep` が ASCII でなければ '.'
+pub fn fmt_fixed_sep(
+    value_scaled: u32,
+    int_digits: u8,
+    frac_digits: u8,
+    sep: u8,
+    buf: &mut [u8],
+) -> &str {
+    let sep = if sep.is_ascii() { sep } else { b'.' };
+    let int_digits = int_digits.clamp(1, MAX_DIGITS);
+    let frac_digits = frac_digits.min(MAX_DIGITS);
+    // 19 桁同士の (10^19)·(10^19) でも溢れないよう u128 で
+    let scale = 10u128.pow(frac_digits as u32);
+    let int_max = 10u128.pow(int_digits as u32) - 1;
+    let value = if value_scaled as u128 / scale > int_max {
+        (int_max + 1) * scale - 1 // 飽和: 全桁 9
+    } else {
+        value_scaled as u128
+    };
+
+    let mut len = 0;
+    let mut put = |b: u8| {
+        if len < buf.len() {
+            buf[len] = b;
+            len += 1;
+        }
+    };
+    let int_part = value / scale;
+    for i in (0..int_digits as u32).rev() {
+        put(b'0' + ((int_part / 10u128.pow(i)) % 10) as u8);
+    }
+    if frac_digits > 0 {
+        put(sep);
+        let frac_part = value % scale;
+        for i in (0..frac_digits as u32).rev() {
+            put(b'0' + ((frac_part / 10u128.pow(i)) % 10) as u8);
+        }
+    }
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// 整数部 `max` までを表すのに必要な桁数（最低 `min_digits`）。定数の桁幅を最大値から決めるため
+/// 例: `int_digits_for(20_000, 4)` → 5、`int_digits_for(2_000, 4)` → 4
+pub const fn int_digits_for(max: u64, min_digits: u8) -> u8 {
+    let mut digits = 1;
+    let mut x = max / 10;
+    while x > 0 {
+        digits += 1;
+        x /= 10;
+    }
+    if digits < min_digits {
+        min_digits
+    } else {
+        digits
+    }
+}
+
+/// `fmt_fixed` と同じ幅のダッシュ（値がないとき用、例: `dashes(4, 1, &mut buf)` → `"----.-"`）
+/// 小数点は `DECIMAL_SEP`。バッファが足りない分は切り捨てる。桁数の丸めは `fmt_fixed` と同じ
+pub fn dashes(int_digits: u8, frac_digits: u8, buf: &mut [u8]) -> &str {
+    let int_digits = int_digits.clamp(1, MAX_DIGITS);
+    let frac_digits = frac_digits.min(MAX_DIGITS);
+    let mut len = 0;
+    let mut put = |b: u8| {
+        if len < buf.len() {
+            buf[len] = b;
+            len += 1;
+        }
+    };
+    for _ in 0..int_digits {
+        put(b'-');
+    }
+    if frac_digits > 0 {
+        put(DECIMAL_SEP);
+        for _ in 0..frac_digits {
+            put(b'-');
+        }
+    }
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(value_scaled: u32, int_digits: u8, frac_digits: u8) -> String {
+        let mut buf = [0u8; 48];
+        fmt_fixed_sep(value_scaled, int_digits, frac_digits, b'.', &mut buf).into()
+    }
+
+    #[test]
+    fn fmt_fixed_zero_pads_both_parts() {
+        assert_eq!(fixed(3300, 2, 3), "03.300");
+        assert_eq!(fixed(5020, 2, 3), "05.020");
+        assert_eq!(fixed(7, 4, 1), "0000.7");
+        assert_eq!(fixed(5, 0, 0), "5");
+        assert_eq!(fixed(123, 3, 0), "123");
+    }
+
+    #[test]
+    fn fmt_fixed_saturates_to_all_nines() {
+        assert_eq!(fixed(123_456, 2, 3), "99.999");
+        assert_eq!(fixed(99_999, 2, 3), "99.999");
+        assert_eq!(fixed(100_000, 2, 3), "99.999");
+        assert_eq!(fixed(u32::MAX, 19, 19).len(), 39);
+    }
+
+    #[test]
+    fn fmt_fixed_clamps_digit_counts() {
+        assert_eq!(fixed(0, 0, 2), "0.00");
+        assert_eq!(fixed(1, 30, 0).len(), MAX_DIGITS as usize);
+        assert_eq!(fixed(1, 1, 30).len(), 1 + 1 + MAX_DIGITS as usize);
+    }
+
+    #[test]
+    fn fmt_fixed_truncates_to_buffer() {
+        let mut buf = [0u8; 4];
+        assert_eq!(fmt_fixed_sep(3300, 2, 3, b'.', &mut buf), "03.3");
+    }
+
+    #[test]
+    fn fmt_fixed_uses_decimal_sep() {
+        let mut buf = [0u8; 8];
+        let s = fmt_fixed(3300, 2, 3, &mut buf);
+        assert_eq!(s.as_bytes()[2], DECIMAL_SEP);
+    }
+
+    #[test]
+    fn round_scaled_rounds_instead_of_truncating() {
+        assert_eq!(round_scaled(0.29, 2), 29);
+        assert_eq!(round_scaled(12.3456, 3), 12_346);
+        assert_eq!(round_scaled(-1.0, 2), 0);
+        assert_eq!(round_scaled(f32::NAN, 2), 0);
+        assert_eq!(round_scaled(1e12, 3), u32::MAX);
+    }
+
+    #[test]
+    fn same_value_with_dot_and_comma() {
+        let (mut a, mut b) = ([0u8; 8], [0u8; 8]);
+        assert_eq!(fmt_fixed_sep(3300, 2, 3, b'.', &mut a), "03.300");
+        assert_eq!(fmt_fixed_sep(3300, 2, 3, b',', &mut b), "03,300");
+        // ASCII でない区切りは '.'、小数部なしなら区切りも出ない
+        assert_eq!(fmt_fixed_sep(3300, 2, 3, 0xB7, &mut a), "03.300");
+        assert_eq!(fmt_fixed_sep(42, 3, 0, b',', &mut b), "042");
+    }
+
+    #[test]
+    fn high_current_shunt_widens_instead_of_capping() {
+        // 20 A のシャントなら整数部 5 桁: 15000 mA は頭打ちにならない
+        const I_DIGITS: u8 = int_digits_for(20_000, 4);
+        assert_eq!(I_DIGITS, 5);
+        assert_eq!(fixed(150_000, I_DIGITS, 1), "15000.0");
+        // 既定の 4 桁のままなら 9999.9 に飽和していた
+        assert_eq!(fixed(150_000, int_digits_for(2_000, 4), 1), "9999.9");
+        assert_eq!(
+            (
+                int_digits_for(0, 1),
+                int_digits_for(9, 1),
+                int_digits_for(10, 1)
+            ),
+            (1, 1, 2)
+        );
+    }
+
+    #[test]
+    fn dashes_match_fixed_width_when_power_disabled() {
+        let mut buf = [0u8; 48];
+        let d = dashes(4, 1, &mut buf);
+        assert_eq!(d.len(), 6);
+        assert_eq!(d.replace(DECIMAL_SEP as char, "."), "----.-");
+        // 電流/電力の欄と同じ幅になる（桁数の丸めも同じ）
+        for (int_digits, frac_digits) in [(4, 1), (5, 1), (0, 0), (30, 2)] {
+            let width = fixed(0, int_digits, frac_digits).len();
+            assert_eq!(dashes(int_digits, frac_digits, &mut buf).len(), width);
+        }
+        let mut short = [0u8; 3];
+        assert_eq!(dashes(4, 1, &mut short), "---");
+    }
+}
@@ -0,0 +1,108 @@
+//! 計測値のバイナリフレーム（feature `binary`）
+//! - 高レートのログ向け。ASCII より小さく、ホスト側で CRC により破損を検出できる
+//! - 配置（リトルエンディアン、全 16 バイト）:
+//!   `[0]` 同期バイト `SYNC` / `[1..5]` elapsed_ms u32 / `[5..7]` v_mv i16 /
+//!   `[7..11]` i_ua i32 / `[11..15]` p_uw i32 / `[15]` CRC8（`[1..15]` の 14 バイトに対して）
+
+#![allow(dead_code)]
+
+/// フレーム先頭の同期バイト
+pub const SYNC: u8 = 0xA5;
+
+/// フレーム長（同期 1 + 本体 14 + CRC 1）
+pub const FRAME_LEN: usize = 16;
+
+/// 1 フレームに載せる値
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct FrameSample {
+    pub elapsed_ms: u32,
+    pub v_mv: i16,
+    pub i_ua: i32,
+    pub p_uw: i32,
+}
+
+/// `sample` を `buf` に詰め、書いたバイト数（常に `FRAME_LEN`）を返す
+pub fn encode_frame(sample: &FrameSample, buf: &mut [u8; FRAME_LEN]) -> usize {
+    buf[0] = SYNC;
+    buf[1..5].copy_from_slice(&sample.elapsed_ms.to_le_bytes());
+    buf[5..7].copy_from_slice(&sample.v_mv.to_le_bytes());
+    buf[7..11].copy_from_slice(&sample.i_ua.to_le_bytes());
+    buf[11..15].copy_from_slice(&sample.p_uw.to_le_bytes());
+    buf[15] = crc8(&buf[1..15]);
+    FRAME_LEN
+}
+
+/// フレームを復元。同期バイトか CRC が合わなければ `None`
+pub fn decode_frame(buf: &[u8; FRAME_LEN]) -> Option<FrameSample> {
+    if buf[0] != SYNC || crc8(&buf[1..15]) != buf[15] {
+        return None;
+    }
+    Some(FrameSample {
+        elapsed_ms: u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]),
+        v_mv: i16::from_le_bytes([buf[5], buf[6]]),
+        i_ua: i32::from_le_bytes([buf[7], buf[8], buf[9], buf[10]]),
+        p_uw: i32::from_le_bytes([buf[11], buf[12], buf[13], buf[14]]),
+    })
+}
+
+/// CRC-8（多項式 0x07、初期値 0x00、反転なし = CRC-8/SMBUS）
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_round_trip_and_layout() {
+        let s = FrameSample {
+            elapsed_ms: 0x0102_0304,
+            v_mv: -5_020,
+            i_ua: -123_456,
+            p_uw: i32::MAX,
+        };
+        let mut buf = [0u8; FRAME_LEN];
+        assert_eq!(encode_frame(&s, &mut buf), FRAME_LEN);
+        assert_eq!(buf[0], SYNC);
+        assert_eq!(&buf[1..5], &[0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(decode_frame(&buf), Some(s));
+    }
+
+    #[test]
+    fn frame_rejects_bad_sync_or_crc() {
+        let mut buf = [0u8; FRAME_LEN];
+        encode_frame(
+            &FrameSample {
+                elapsed_ms: 1,
+                v_mv: 2,
+                i_ua: 3,
+                p_uw: 4,
+            },
+            &mut buf,
+        );
+        let mut corrupt = buf;
+        corrupt[8] ^= 0x10;
+        assert_eq!(decode_frame(&corrupt), None);
+        let mut unsynced = buf;
+        unsynced[0] = 0x00;
+        assert_eq!(decode_frame(&unsynced), None);
+    }
+
+    #[test]
+    fn crc8_matches_smbus_check_value() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc8(&[]), 0x00);
+    }
+}
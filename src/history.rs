@@ -0,0 +1,148 @@
+//! 直近 N サンプルの履歴（no_std、アロケーションなし）
+//! - グラフ/スパークライン/傾向分析のために計測値（`Measurement` など `Copy` な値）をそのまま保持
+//! - 固定長リングバッファ。満杯時は最古のサンプルを上書きする
+//! - `iter_recent()` は古い順（最後が最新）
+//! - 窓統計（`metrics::RollingStats`）やスパークライン（`termviz::Sparkline`）もこの上に作る
+
+#![allow(dead_code)]
+
+/// 直近 N サンプルの履歴
+#[derive(Clone, Copy)]
+pub struct SampleHistory<T: Copy, const N: usize> {
+    /// 未使用の枠は `fill` のまま（`len` を超える位置は読まない）
+    buf: [T; N],
+    /// 次に書き込む位置（満杯時は最古のサンプルの位置）
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> SampleHistory<T, N> {
+    /// 空の履歴。`fill` は未使用の枠を埋めるだけの値（読み出しには出てこない）
+    pub const fn filled(fill: T) -> Self {
+        Self {
+            buf: [fill; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// サンプルを追加（満杯なら最古を上書き）
+    pub fn push(&mut self, x: T) {
+        if N == 0 {
+            return;
+        }
+        self.buf[self.head] = x;
+        self.head = (self.head + 1) % N;
+        if self.len < N {
+            self.len += 1;
+        }
+    }
+
+    /// 保持しているサンプル数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 空か
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 満杯か（以降の push は最古を上書きする）
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// 古い順に i 番目のサンプル（`i >= len` なら `None`）
+    pub fn get(&self, i: usize) -> Option<T> {
+        if i >= self.len {
+            return None;
+        }
+        let start = (self.head + N - self.len) % N;
+        Some(self.buf[(start + i) % N])
+    }
+
+    /// 最新のサンプル（空なら `None`）
+    pub fn latest(&self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.buf[(self.head + N - 1) % N])
+    }
+
+    /// 保持しているサンプルを古い順に返す
+    pub fn iter_recent(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).filter_map(move |i| self.get(i))
+    }
+
+    /// 保持しているサンプル（順不同）。平均/最小/最大のように順序のいらない集計用
+    /// 満杯になるまでは先頭から詰めて書くので、先頭 `len` 個がそのまま有効なサンプル
+    pub fn as_unordered(&self) -> &[T] {
+        &self.buf[..self.len]
+    }
+
+    /// 全サンプルを捨てる（中身は残るが読み出されない）
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Default for SampleHistory<T, N> {
+    fn default() -> Self {
+        Self::filled(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sample::Measurement;
+
+    fn recent<const N: usize>(h: &SampleHistory<u32, N>) -> Vec<u32> {
+        h.iter_recent().collect()
+    }
+
+    #[test]
+    fn iter_recent_is_oldest_to_newest_across_wraparound() {
+        let mut h: SampleHistory<u32, 4> = SampleHistory::default();
+        assert!(h.is_empty() && h.latest().is_none());
+        for x in 1..=3 {
+            h.push(x);
+        }
+        assert_eq!(recent(&h), [1, 2, 3]);
+        assert!(!h.is_full());
+        for x in 4..=6 {
+            h.push(x);
+        }
+        assert!(h.is_full());
+        assert_eq!(recent(&h), [3, 4, 5, 6]);
+        assert_eq!(
+            (h.get(0), h.get(3), h.get(4), h.latest()),
+            (Some(3), Some(6), None, Some(6))
+        );
+        let mut unordered = h.as_unordered().to_vec();
+        unordered.sort();
+        assert_eq!(unordered, [3, 4, 5, 6]);
+        h.clear();
+        assert_eq!(recent(&h), []);
+    }
+
+    #[test]
+    fn holds_measurements_by_value() {
+        let mut h = SampleHistory::<Measurement, 2>::filled(Measurement::ZERO);
+        for bus_mv in [1_000, 2_000, 3_000] {
+            h.push(Measurement {
+                bus_mv,
+                ..Measurement::ZERO
+            });
+        }
+        let v: Vec<i32> = h.iter_recent().map(|m| m.bus_mv).collect();
+        assert_eq!(v, [2_000, 3_000]);
+        // 長さ 0 でも push で壊れない
+        let mut empty = SampleHistory::<u32, 0>::default();
+        empty.push(1);
+        assert!(empty.is_empty());
+    }
+}
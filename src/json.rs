@@ -0,0 +1,129 @@
+//! JSON Lines 出力（feature `json`）
+//! - サンプルごとに1行 `{"rail":"R0","t_ms":…,"v_mv":…,"i_ua":…,"p_uw":…,"e_uws":…}`
+//! - serde なしの手書き。キーの並びは固定、値は整数のみ（内部の固定小数点表現をそのまま出す）
+//! - 未校正の `i_ua` / `p_uw` は `null`
+
+use crate::ascii;
+
+/// 1行の最大長（各値の最大桁 + キー + 区切り）
+pub const LINE_MAX: usize = 128;
+
+/// 1サンプルを JSON オブジェクト1行として `buf` に書き込み、書いたバイト数を返す（改行なし）
+/// バッファが足りない分は切り捨てる（`LINE_MAX` 以上を渡せば切れない）
+/// `rail` はエスケープしないので、`"` や `\` を含まない名前を渡すこと
+pub fn format_sample(
+    rail: &str,
+    t_ms: u64,
+    v_mv: i32,
+    i_ua: Option<i32>,
+    p_uw: Option<i32>,
+    e_uws: i64,
+    buf: &mut [u8],
+) -> usize {
+    let mut w = Writer {
+        out: ascii::Writer::new(buf),
+        first: true,
+    };
+    w.push(b'{');
+    w.key("rail");
+    w.push(b'"');
+    w.push_str(rail);
+    w.push(b'"');
+    w.key("t_ms");
+    w.push_u64(t_ms);
+    w.key("v_mv");
+    w.push_i64(v_mv as i64);
+    w.key("i_ua");
+    w.push_opt(i_ua);
+    w.key("p_uw");
+    w.push_opt(p_uw);
+    w.key("e_uws");
+    w.push_i64(e_uws);
+    w.push(b'}');
+    w.out.len()
+}
+
+/// キー区切りつきの書き込み先（2つ目以降のキーの前にだけ `,` を入れる）
+struct Writer<'a> {
+    out: ascii::Writer<'a>,
+    first: bool,
+}
+
+impl Writer<'_> {
+    fn push(&mut self, b: u8) {
+        self.out.push(b);
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn push_u64(&mut self, x: u64) {
+        self.out.push_u64(x);
+    }
+
+    fn push_i64(&mut self, x: i64) {
+        self.out.push_i64(x);
+    }
+
+    /// `"key":`（先頭以外は `,` を前置）
+    fn key(&mut self, k: &str) {
+        if !self.first {
+            self.push(b',');
+        }
+        self.first = false;
+        self.push(b'"');
+        self.push_str(k);
+        self.push_str("\":");
+    }
+
+    fn push_opt(&mut self, x: Option<i32>) {
+        match x {
+            Some(x) => self.push_i64(x as i64),
+            None => self.push_str("null"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_sample_exact_string() {
+        let mut buf = [0u8; LINE_MAX];
+        let n = format_sample(
+            "R0",
+            1_500,
+            5_020,
+            Some(-12_300),
+            Some(61_746),
+            9_000_000_000,
+            &mut buf,
+        );
+        assert_eq!(
+            core::str::from_utf8(&buf[..n]).unwrap(),
+            r#"{"rail":"R0","t_ms":1500,"v_mv":5020,"i_ua":-12300,"p_uw":61746,"e_uws":9000000000}"#
+        );
+    }
+
+    #[test]
+    fn format_sample_writes_null_when_uncalibrated_and_fits_line_max() {
+        let mut buf = [0u8; LINE_MAX];
+        let n = format_sample("R1", 0, 0, None, None, 0, &mut buf);
+        assert_eq!(
+            &buf[..n],
+            br#"{"rail":"R1","t_ms":0,"v_mv":0,"i_ua":null,"p_uw":null,"e_uws":0}"#
+        );
+        let n = format_sample(
+            "R2",
+            u64::MAX,
+            i32::MIN,
+            Some(i32::MIN),
+            Some(i32::MIN),
+            i64::MIN,
+            &mut buf,
+        );
+        assert!(n < LINE_MAX);
+    }
+}
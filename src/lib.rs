@@ -0,0 +1,19 @@
+//! 計測値の積算・集計・整形のロジック（HAL に依存しない部分）
+//! - `main.rs` はハードウェア（I2C/GPIO/タイマ/USB/フラッシュ）をここの関数へつなぐだけにする
+//! - ターゲットに依存しないので、ホストで `cargo test --lib --target <ホストのトリプル>` として試験できる
+//!   （例: `cargo test --lib --target x86_64-unknown-linux-gnu`）
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod ascii;
+pub mod config;
+pub mod csv;
+pub mod device;
+pub mod fmt;
+pub mod frame;
+pub mod history;
+pub mod json;
+pub mod metrics;
+pub mod record;
+pub mod sample;
+pub mod termviz;
@@ -0,0 +1,74 @@
+//! defmt の出力先（`info!`/`warn!`/`error!` はすべて defmt の global_logger を通るので、ここで差し替える）
+//! - 既定の `log-rtt` は defmt-rtt（このモジュールは使わない）
+//! - `log-none`: 何も出さない。プローブをつながない現場の個体で、RTT の初期化/書き込みを待たない
+//! - `log-usb`: defmt のフレームを USB シリアル（`usb_serial::write`）へ流す。ホストでは `defmt-print -e <elf>` で読む
+//!   （送信バッファが詰まると捨てるので、途中が欠けたフレームは次の区切りまで読み飛ばされる）
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[defmt::global_logger]
+struct Logger;
+
+/// 1フレーム分の送り先の状態（acquire〜release の間は割り込み禁止で1つだけ）
+struct Sink {
+    taken: AtomicBool,
+    /// acquire 前に割り込みが有効だったか（release で戻す）
+    irq_was_enabled: UnsafeCell<bool>,
+    #[cfg(feature = "log-usb")]
+    encoder: UnsafeCell<defmt::Encoder>,
+}
+
+// 安全：中身には acquire〜release の間（割り込み禁止中）にしか触らない
+unsafe impl Sync for Sink {}
+
+static SINK: Sink = Sink {
+    taken: AtomicBool::new(false),
+    irq_was_enabled: UnsafeCell::new(false),
+    #[cfg(feature = "log-usb")]
+    encoder: UnsafeCell::new(defmt::Encoder::new()),
+};
+
+/// エンコード済みのバイト列の送り先
+#[cfg(feature = "log-usb")]
+fn emit(bytes: &[u8]) {
+    crate::usb_serial::write(bytes);
+}
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let irq_was_enabled = cortex_m::register::primask::read().is_active();
+        cortex_m::interrupt::disable();
+        if SINK.taken.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+        SINK.taken.store(true, Ordering::Relaxed);
+        // 安全：割り込み禁止中で、taken により他からは触られない
+        unsafe {
+            *SINK.irq_was_enabled.get() = irq_was_enabled;
+            #[cfg(feature = "log-usb")]
+            (*SINK.encoder.get()).start_frame(emit);
+        }
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        #[cfg(feature = "log-usb")]
+        unsafe {
+            (*SINK.encoder.get()).end_frame(emit)
+        };
+        SINK.taken.store(false, Ordering::Relaxed);
+        // 安全：acquire で禁止した割り込みを、もともと有効だったときだけ戻す
+        if unsafe { *SINK.irq_was_enabled.get() } {
+            unsafe { cortex_m::interrupt::enable() };
+        }
+    }
+
+    unsafe fn write(_bytes: &[u8]) {
+        #[cfg(feature = "log-usb")]
+        unsafe {
+            (*SINK.encoder.get()).write(_bytes, emit)
+        };
+    }
+}
@@ -1,40 +1,412 @@
 #![no_std]
 #![no_main]
 
-// 本ファイルは「最小限の機能」に絞った版です。
+// RP2040 + INA219（最大 INA_COUNT 台）の電圧/電流/電力モニタのファームウェア本体。
 // 目的:
-//  - INA219 の電圧/電流/電力を 500ms 間隔で defmt に出力
-//  - 起動からの経過時間（s）と、累計消費電力量（mWh）を併せて表示
+//  - 各レールの電圧/電流/電力を計測周期（既定 500ms、USB シリアルから変更可）ごとに読み、電荷/エネルギーを積算
+//  - 出力は人向けの固定幅の行（VERBOSITY で量を選ぶ）か、機械向けの CSV / JSON Lines / バイナリフレーム（feature）
+//  - 出力先は defmt（RTT / USB シリアル / なし）、1秒ごとの行は USB CDC ACM にも出せる
+//  - 集計窓ごとの統計、試験終了の要約、過電流/低電圧のアラート、2 レール間の変換効率
+//  - 累計値をフラッシュに保存し、電源断・リセットをまたいで積算を続ける
+//  - 待機中は計測周期を引き延ばす（IDLE_CURRENT_UA）。ウォッチドッグで I2C の固着から復帰
 // 設計方針:
-//  - エネルギーは整数で積算（単位: µW・ms）。表示時に mWh へ換算。
-//  - 積算は「前回の有効電力値」を区間一定として dt（ms）で台形ではなく矩形近似。
-//    next_measurement() が毎回新値を返す前提なら誤差は小さい。
+//  - 積算/集計/整形は HAL に依存しない lib 側（`pico_va_monitor`）に置き、ホストで試験する。ここはハードウェアをつなぐだけ
+//  - 積算は固定小数（電荷 µA·s、エネルギー µW·s、`metrics::Accumulators`）。dt は実測で、直近サンプルの I/P による矩形近似
+//  - 読み出しが途切れた区間は SAMPLE_HOLD_PERIODS を過ぎたら積算しない（古い値で水増ししない）
+//  - 設定はビルド時の環境変数（`env_u32!`）と feature。不正な値はコンパイルエラー
+
+use core::cell::{Cell, RefCell};
+use core::num::NonZeroU32;
+
+use cortex_m::interrupt::Mutex;
 
 use cortex_m_rt::entry;
 use defmt::*;
+#[cfg(feature = "log-rtt")]
 use defmt_rtt as _;
 use fugit::RateExtU32 as _;
 use panic_probe as _;
 
 use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+#[cfg(feature = "power-led")]
+use embedded_hal::pwm::SetDutyCycle;
 use hal::{
-    clocks::init_clocks_and_plls, gpio::FunctionI2C, pac, sio::Sio, watchdog::Watchdog, Timer, I2C,
+    clocks::init_clocks_and_plls,
+    gpio::{
+        bank0::{Gpio15, Gpio4, Gpio5},
+        FunctionI2C, FunctionSioInput, Interrupt as GpioInterrupt, Pin, PullUp,
+    },
+    pac::{self, interrupt},
+    sio::Sio,
+    timer::{Alarm, Alarm0, Instant},
+    watchdog::Watchdog,
+    Timer, I2C,
 };
 use rp2040_hal as hal;
 use rp2040_hal::Clock;
 
+// 積算・集計・整形は HAL に依存しないライブラリ側（src/lib.rs）。ここではハードウェアとつなぐだけ
+use pico_va_monitor::device::{config_mismatch, with_retry};
+use pico_va_monitor::env_u32;
+use pico_va_monitor::metrics::wall_clock_hms;
+use pico_va_monitor::sample::{self, Measurement};
+use pico_va_monitor::{csv, fmt, frame, history::SampleHistory, json, metrics, termviz};
+
+#[cfg(any(feature = "log-none", feature = "log-usb"))]
+mod log_sink;
+mod persist;
+#[cfg(feature = "usb-serial")]
+mod usb_serial;
+
+// defmt の出力先はどれか1つ（既定の log-rtt をやめるときは --no-default-features）
+#[cfg(not(any(feature = "log-rtt", feature = "log-none", feature = "log-usb")))]
+compile_error!("select one log backend: log-rtt, log-none or log-usb");
+#[cfg(any(
+    all(feature = "log-rtt", feature = "log-none"),
+    all(feature = "log-rtt", feature = "log-usb"),
+    all(feature = "log-none", feature = "log-usb"),
+))]
+compile_error!("log-rtt, log-none and log-usb are mutually exclusive (use --no-default-features)");
+
 // INA219（同期API）
 use ina219 as ina;
 use ina219::address::Address;
-use ina219::calibration::IntCalibration;
-use ina219::configuration::{BusVoltageRange, Configuration, ShuntVoltageRange};
-use ina219::errors::InitializationErrorReason;
+use ina219::calibration::{IntCalibration, UnCalibrated};
+use ina219::configuration::{BusVoltageRange, Configuration, Resolution, ShuntVoltageRange};
+use ina219::errors::{InitializationErrorReason, MeasurementError};
 
 // ---- 設定定数（必要最小限） ----
-const SHUNT_OHMS: f32 = 0.1; // シャント抵抗 [Ω]
-const MAX_EXPECTED_AMPS: f32 = 2.0; // 最大期待電流 [A]
-const LOOP_MS: u32 = 500; // 計測周期 [ms]
-const INA_ADDR: u8 = 0x44; //INA219の半田によってアドレスが変わります
+// シャント抵抗と最大期待電流はビルド時の環境変数で上書き可能（10進整数、未設定なら既定値、不正な値はコンパイルエラー）
+// 例: SHUNT_UOHM=2000 MAX_EXPECTED_MA=20000 cargo build --release
+const SHUNT_UOHM: u32 = env_u32!("SHUNT_UOHM", 100_000); // シャント抵抗 [µΩ]（既定 0.1Ω）
+const MAX_EXPECTED_MA: u32 = env_u32!("MAX_EXPECTED_MA", 2_000); // 最大期待電流 [mA]
+                                                                 // current_LSB[µA/bit] は MAX_EXPECTED_MA / 2^15 で見積（切り捨て）
+const CURRENT_LSB_UA: i64 = MAX_EXPECTED_MA as i64 * 1000 / 32768;
+// 電流/電力を測るか（ビルド時の環境変数 MEASURE_POWER、既定 1）。0 なら校正を一切行わず未校正のまま開き、
+// 経過時間とバス電圧だけを表示する（良いシャントがなく、電圧だけを監視したいとき）
+const MEASURE_POWER: bool = env_u32!("MEASURE_POWER", 1) != 0;
+// IntCalibration が受け付ける範囲（0.04096/(LSB×R) が 2..=65535 に収まる）をコンパイル時に検査（校正しないなら不要）
+const _: () = core::assert!(
+    !MEASURE_POWER
+        || CURRENT_LSB_UA >= 1
+            && CURRENT_LSB_UA as u64 * SHUNT_UOHM as u64 >= 40_960_000_000 / 65_535
+            && CURRENT_LSB_UA as u64 * SHUNT_UOHM as u64 <= 40_960_000_000 / 2,
+    "current_LSB x SHUNT_UOHM out of INA219 calibration range"
+);
+// 起動時の RTT アタッチ猶予 [ms]（ビルド時の環境変数 STARTUP_DELAY_MS、既定 500）。手動でキャプチャを始めるなら長めに
+const STARTUP_DELAY_MS: u32 = env_u32!("STARTUP_DELAY_MS", 500);
+// 最初の有効サンプル待ち [ms]（ビルド時の環境変数 WAIT_FIRST_SAMPLE_MS、既定 0 = 待たない）
+// 主レールが 0 でない電流（未校正ならシャント電圧）を返すまで計測開始を遅らせ、積算の t=0 を実データにそろえる
+const WAIT_FIRST_SAMPLE_MS: u32 = env_u32!("WAIT_FIRST_SAMPLE_MS", 0);
+const WAIT_FIRST: bool = WAIT_FIRST_SAMPLE_MS != 0;
+const LOOP_MS: u32 = 500; // 計測周期 [ms]（起動時の値。usb-serial 有効時は `p250` などで実行中に変更可）
+                          // 実行中に変更できる周期の範囲 [ms]。下限は INA219 の変換時間（切り上げ）、上限はウォッチドッグの上限の半分
+const MIN_LOOP_MS: u32 =
+    (SHUNT_ADC.conversion_time_us() + BUS_ADC.conversion_time_us()).div_ceil(1000);
+const MAX_LOOP_MS: u32 = 4_000;
+// 待機中の周期引き延ばし: 主レールの |I| が IDLE_CURRENT_UA 未満のサンプルが IDLE_QUIET_SAMPLES 回続くたびに
+// 周期を倍（上限 MAX_LOOP_MS）、閾値以上が来たら即座に元の周期へ。ビルド時の環境変数、既定 0 = 無効
+const IDLE_CURRENT_UA: u32 = env_u32!("IDLE_CURRENT_UA", 0);
+const IDLE_THRESHOLD_UA: Option<NonZeroU32> = NonZeroU32::new(IDLE_CURRENT_UA); // None = 引き延ばし無効
+const IDLE_QUIET_SAMPLES: u32 = 10;
+// 積算器の微小電流カットオフ [mA]: |I| がこれ未満の区間は電荷を積算せず、デューティ比でも「休止」と数える
+// ビルド時の環境変数、既定は IDLE_CURRENT_UA を mA に切り捨てた値（どちらも未設定なら 0 = カットオフなし、デューティ比は常に 100%）
+const CURRENT_CUTOFF_MA: u32 = env_u32!("CURRENT_CUTOFF_MA", IDLE_CURRENT_UA / 1000);
+// 試験終了の要約: 負荷があった後、主レールの |I| が END_IDLE_UA 未満のまま END_IDLE_MS 続いたら
+// 累計エネルギー・V/I の平均/最小/最大・最大電力・デューティ比・電池本数換算を複数行で出す（計測はそのまま続ける）
+// ビルド時の環境変数、END_IDLE_MS の既定 30000、0 = 無効
+const END_IDLE_UA: u32 = env_u32!("END_IDLE_UA", 1_000);
+const END_IDLE_MS: u32 = env_u32!("END_IDLE_MS", 30_000);
+// 微小電力のカットオフ [mW]: |P| がこれ未満の区間はエネルギーを積算しない（待機時のセンサノイズ対策）
+// 電荷（mAh）には効かない。ビルド時の環境変数、既定 0 = 無効。例: POWER_CUTOFF_MW=1
+const POWER_CUTOFF_MW: u32 = env_u32!("POWER_CUTOFF_MW", 0);
+// CSV 出力モード（feature `csv`）。有効時は人向けの行を止め、サンプルごとに CSV 1行を出す
+const OUTPUT_CSV: bool = cfg!(feature = "csv");
+// 固定レートの CSV（feature `csv-fixed-rate`）。サンプルごとの行の代わりに、計測周期ごとに全レール1行ずつ
+// 読めなかった周（新データなし/エラー/外れ値/切断中）は直前の値を `fresh=0` で繰り返す
+const CSV_FIXED_RATE: bool = cfg!(feature = "csv-fixed-rate");
+// バイナリフレーム出力モード（feature `binary`）。サンプルごとに 16 バイトのフレームを出す
+const OUTPUT_BINARY: bool = cfg!(feature = "binary");
+// JSON Lines 出力モード（feature `json`）。サンプルごとに JSON オブジェクト1行を出す
+const OUTPUT_JSON: bool = cfg!(feature = "json");
+// 集計窓ごとの CRC16 付きの行（feature `minute-crc`）。ノイズの多いリンクでも壊れた行を捨てられる
+const OUTPUT_MINUTE_CRC: bool = cfg!(feature = "minute-crc");
+// 計測の合間に INA219 の電源を切る（feature `power-gate`、GPIO18 を High で INA219 の VCC を入れる）
+// 毎周: 電源投入 → INA_POWER_UP_MS 待ち → 校正/設定の書き直し（電源断で消えるため）→ 変換時間待ち → 読み出し → 電源断
+const POWER_GATE: bool = cfg!(feature = "power-gate");
+const INA_POWER_UP_MS: u32 = 1; // 電源投入から I2C に応答できるまで [ms]（データシートの起動時間 40 µs に余裕を見る）
+                                // 電源投入から読み出しまでの時間 [ms]。この間だけ積算し、電源を切っている間は 0 として扱う
+const GATE_ON_MS: u64 = (INA_POWER_UP_MS + MIN_LOOP_MS + 1) as u64;
+// 人向けの固定幅の行（機械向けモードがどれも無効なとき）
+const OUTPUT_HUMAN: bool = !OUTPUT_CSV && !OUTPUT_BINARY && !OUTPUT_JSON;
+// 人向けの出力の量（OUTPUT_HUMAN のとき。警告/エラーと起動時のログは常に出す）
+// ビルド時の環境変数 VERBOSITY: 0 = Quiet, 1 = Summary, 2 = Normal（既定）, 3 = Verbose
+const VERBOSITY: Verbosity = match env_u32!("VERBOSITY", 2) {
+    0 => Verbosity::Quiet,
+    1 => Verbosity::Summary,
+    2 => Verbosity::Normal,
+    3 => Verbosity::Verbose,
+    _ => core::panic!("VERBOSITY must be 0 (Quiet), 1 (Summary), 2 (Normal) or 3 (Verbose)"),
+};
+
+/// 人向けの出力の量（下ほど多い。各段は上の段の出力をすべて含む）
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// 集計窓ごとの平均の行だけ
+    Quiet,
+    /// + 集計窓のサンプル統計 / 最大需要電力 / クーロンカウンタ式の残量
+    Summary,
+    /// + 1秒ごとの行と変換効率（従来どおり）
+    Normal,
+    /// + サンプルごとの生の値（シャント/バス電圧レジスタ、電流/電力の整数値）
+    Verbose,
+}
+// ウォッチドッグ: この時間フィードがなければ自動リセット（I2C が固まったまま戻らない場合の保険）
+// 1周 = 読み出し + 周期待ち（≒LOOP_MS）なので周期の2倍を目安にする。RP2040 の上限は約 8.3 秒
+// ただし周期は `p<ms>` や待機中の延長で変わり、短い周期（数十 ms）の2倍ではフラッシュ消去などの長い周に足りないため、
+// WORST_CASE_ITER_MS を下限にする（`watchdog_ms`）
+const WATCHDOG_MS: u32 = watchdog_ms(LOOP_MS);
+const _: () = core::assert!(
+    WATCHDOG_MS > LOOP_MS && WATCHDOG_MS <= 8_300,
+    "WATCHDOG_MS out of range"
+);
+// 1周の最悪の所要時間 [ms]。フィードからフィードまでに入りうる長い処理の合計
+// - 累計値の保存: セクタ消去（W25Q16JV の 4 KiB 消去の最大 400 ms）+ 1ページの書き込み（最大 3 ms）
+// - 読み出しの再試行の待ち（レールごとに 2, 4, ... ms）、リセットボタンのデバウンス、INA219 の電源投入待ち
+// - I2C バス復旧（µs 単位の手動クロック）や再初期化の I2C 転送の余裕として 50 ms
+const FLASH_ERASE_MAX_MS: u32 = 400;
+const FLASH_PROGRAM_MAX_MS: u32 = 3;
+const WORST_CASE_ITER_MS: u32 = FLASH_ERASE_MAX_MS
+    + FLASH_PROGRAM_MAX_MS
+    + INA_COUNT as u32 * (INA_RETRY_BASE_MS << INA_READ_ATTEMPTS)
+    + BUTTON_DEBOUNCE_MS
+    + GATE_ON_MS as u32
+    + 50;
+const _: () = core::assert!(
+    WORST_CASE_ITER_MS <= 8_300,
+    "WORST_CASE_ITER_MS exceeds the RP2040 watchdog limit"
+);
+const _: () = core::assert!(
+    watchdog_ms(MAX_LOOP_MS) <= 8_300,
+    "MAX_LOOP_MS too long for the watchdog"
+);
+
+/// 周期 `loop_ms` に対するウォッチドッグの時間 [ms]（周期の2倍、ただし WORST_CASE_ITER_MS 以上）
+const fn watchdog_ms(loop_ms: u32) -> u32 {
+    let ms = loop_ms.saturating_mul(2);
+    if ms > WORST_CASE_ITER_MS {
+        ms
+    } else {
+        WORST_CASE_ITER_MS
+    }
+}
+// ADC 分解能/平均回数（Avg2〜Avg128 は 12bit を内部平均。回数を増やすほど低ノイズだが変換が遅い）
+// 1回の変換時間はシャント+バスの合計（Avg16 なら 8.51ms ×2 ≒ 17ms）。
+// LOOP_MS がこれより短いと next_measurement() が Ok(None)（新データなし）を返し続けるため、
+// 変換時間 ≦ LOOP_MS をコンパイル時に検査する。微小電流の計測では平均回数を増やすとよい。
+const SHUNT_ADC: Resolution = Resolution::Avg16;
+const BUS_ADC: Resolution = Resolution::Avg16;
+const _: () = core::assert!(
+    SHUNT_ADC.conversion_time_us() + BUS_ADC.conversion_time_us() <= LOOP_MS * 1000,
+    "INA219 conversion time exceeds LOOP_MS"
+);
+// 測定レンジ（32V / シャント±320mV = 最大ゲイン）。フルスケールの FULL_SCALE_WARN_PCT % を超えたら1回だけ警告する
+// （精度が落ちる/振り切れる手前なので、BusVoltageRange / ShuntVoltageRange やシャント抵抗の見直しの目安）
+const BUS_RANGE: BusVoltageRange = BusVoltageRange::Fsr32v;
+const SHUNT_RANGE: ShuntVoltageRange = ShuntVoltageRange::Fsr320mv;
+const BUS_FSR_MV: i32 = BUS_RANGE.range_v().end as i32 * 1000;
+const SHUNT_FSR_UV: i32 = *SHUNT_RANGE.range_mv().end() as i32 * 1000;
+const FULL_SCALE_WARN_PCT: i32 = 90;
+// 起動時のノイズ測定のサンプル数（無負荷/待機状態で接続しておくと実効分解能の目安になる）
+const NOISE_SAMPLES: u32 = 64;
+const INA_READ_ATTEMPTS: u32 = 3; // 読み出しの最大試行回数（失敗が続いたら再初期化）
+const INA_RETRY_BASE_MS: u32 = 2; // 再試行の待ち [ms]（試行ごとに倍: 2, 4, ...）
+                                  // I2C0 のクロック [kHz]（ビルド時の環境変数、既定 100 = 安定性重視）。配線が短くプルアップが強ければ 400 で
+                                  // 1 周の読み出し時間が縮み、複数台でも周期を詰められる。Standard (100) / Fast (400) mode 以外はビルドエラー
+const I2C_FREQ_KHZ: u32 = env_u32!("I2C_FREQ_KHZ", 100);
+const _: () = core::assert!(
+    I2C_FREQ_KHZ == 100 || I2C_FREQ_KHZ == 400,
+    "I2C_FREQ_KHZ must be 100 or 400"
+);
+const I2C_RECOVERY_ERRORS: u32 = 5; // 連続でこの回数読み出しに失敗したらバス復旧を試みる
+const INA_DISCONNECT_ERRORS: u32 = 10; // バス復旧をはさんでも連続でこの回数失敗したら切断とみなす
+const RECENT_SAMPLES: usize = 8; // 切断時にログへ残す直近の計測値の数（レール別）
+const INA_REDETECT_MS: u64 = 1000; // 切断中のレールを再検出（init_ina219）する間隔 [ms]
+                                   // 直近サンプルで積算してよい期間（計測周期の何倍か）。これを過ぎた読み出しの空白は積算しない
+const SAMPLE_HOLD_PERIODS: u64 = 2;
+// 1 周の dt の上限（計測周期の何倍か）。タイマの異常で巨大な dt が来ても積算を一度に膨らませない
+const MAX_DT_PERIODS: u64 = 10;
+// 外れ値除去（I2C のビット化けなどによる単発の異常電流を積算しない）: 直近 OUTLIER_WINDOW サンプルの
+// |電流| の中央値の OUTLIER_FACTOR 倍を超えたら捨てる（ビルド時の環境変数、既定 0 = 無効。例: OUTLIER_FACTOR=5）
+// 1秒行の間引き: PRINT_EVERY サンプルごとに、その間の V/Vsh/I/P の平均で1行出す（積算は毎サンプル）
+// ビルド時の環境変数、既定 0 = 従来どおり1秒ごとに最新サンプルを表示。長時間のログを減らすなら 20 など
+const PRINT_EVERY: u32 = env_u32!("PRINT_EVERY", 0);
+const PRINT_DECIMATED: bool = PRINT_EVERY != 0;
+const OUTLIER_FACTOR: u32 = env_u32!("OUTLIER_FACTOR", 0);
+const OUTLIER_WINDOW: usize = 16;
+const OUTLIER_FLOOR_UA: f32 = 1_000.0; // |電流| がこれ以下なら判定しない [µA]
+                                       // 集計窓 [ms]（ビルド時の環境変数 AGG_WINDOW_MS、既定 60000 = 1 分）。短い試験なら 10000、長時間なら 3600000 など
+const AGG_WINDOW_MS: u32 = env_u32!("AGG_WINDOW_MS", 60_000);
+const _: () = core::assert!(AGG_WINDOW_MS > 0, "AGG_WINDOW_MS must be > 0");
+// 窓のエネルギー（µW·ms, i64）は入力の上限 i32::MAX µW が窓いっぱい続いても飽和しない（窓ごとに 0 に戻す）
+const _: () = core::assert!(
+    AGG_WINDOW_MS as u64 <= metrics::MinuteAggregator::max_window_ms(i32::MAX as i64),
+    "AGG_WINDOW_MS too long: window energy could saturate"
+);
+// 集計行の見出し（既定の 1 分窓は従来どおり「NN分目 … 1分消費」）
+const AGG_INDEX_LABEL: &str = if AGG_WINDOW_MS == 60_000 {
+    "分目"
+} else {
+    "区間目"
+};
+const AGG_USAGE_LABEL: &str = if AGG_WINDOW_MS == 60_000 {
+    "1分消費"
+} else {
+    "区間消費"
+};
+// フラッシュへの保存間隔 [ms]（集計窓とは独立。短い窓でも書き込み回数を増やさない）
+// 最大需要電力の窓 [s]（主レール、直近 DEMAND_WINDOW_S 秒の平均電力の最大値）。窓はサンプル数で持つので
+// 計測周期を変えると実際の秒数もずれる（LOOP_MS 基準）
+const DEMAND_WINDOW_S: u32 = 15;
+const DEMAND_SAMPLES: usize = (DEMAND_WINDOW_S * 1000 / LOOP_MS) as usize;
+const _: () = core::assert!(DEMAND_SAMPLES > 0, "DEMAND_WINDOW_S shorter than LOOP_MS");
+const PERSIST_INTERVAL_MS: u64 = 60_000;
+// 壁時計の起点（ビルド時の環境変数 START_EPOCH_S、起動時点の時刻を秒で）。未設定なら壁時計は表示しない
+// RP2040 には電池バックアップの RTC がないため、例えば `START_EPOCH_S=$(date +%s)` で焼き込む（UTC）。
+// 現地時刻で表示したい場合は時差を足した値を渡す
+const START_EPOCH_S: Option<u32> = match option_env!("START_EPOCH_S") {
+    Some(_) => Some(env_u32!("START_EPOCH_S", 0)),
+    None => None,
+};
+// リセットボタン: GPIO15（ピン20）と GND の間に押しボタン。内部プルアップでアクティブロー
+// 押すと累計（エネルギー/経過時間）と1分集計をゼロから数え直す
+const BUTTON_DEBOUNCE_MS: u32 = 20; // 立ち下がりからこの時間後も Low なら押下とみなす（短いバウンスは無視）
+                                    // 電力インジケータ LED（feature `power-led`）: GPIO25（Pico 基板上の LED、PWM4 B）。主レールの電力 / LED_MAX_POWER_MW で明るさを決める
+                                    // Pico W では GPIO25 が CYW43 の WL_CS につながっているため既定では無効（有効にするのは素の Pico のときだけ）
+#[cfg(feature = "power-led")]
+const LED_MAX_POWER_MW: f32 = 2000.0; // この電力以上で全点灯 [mW]
+                                      // 電力バー: 1秒行のあとに `[R0] P |=====>....| 031%` を出す。MAX_POWER_MW [mW] を 100% とする（オートスケールしない）
+                                      // ビルド時の環境変数、既定 2000、0 = 出さない
+const MAX_POWER_MW: u32 = env_u32!("MAX_POWER_MW", 2_000);
+// 見かけの負荷抵抗 RL = V / I は |I| がこれ以上のときだけ表示（微小電流では値が暴れるため）[µA]
+const LOAD_MIN_UA: i32 = 1_000;
+// 1秒行/集計窓の行の I[mA] / P[mW] の整数部の桁数。最大期待電流（とバス電圧レンジ）から決め、
+// 大電流のシャントでも 9999 などに頭打ちにしない（既定の 2 A では従来どおり 4 桁 / 5 桁）
+const I_INT_DIGITS: u8 = fmt::int_digits_for(MAX_EXPECTED_MA as u64, 4);
+const P_INT_DIGITS: u8 =
+    fmt::int_digits_for(MAX_EXPECTED_MA as u64 * (BUS_FSR_MV as u64 / 1000), 5);
+// 1秒行の累計（E=…）の単位。mAh は正味電荷（充電超過の負値は 0 表示）
+const DISPLAY_UNIT: metrics::DisplayUnit = metrics::DisplayUnit::MilliWattHour;
+// 累計の整数部の桁数（小数は 2 桁固定）
+const DISPLAY_INT_DIGITS: u8 = match DISPLAY_UNIT {
+    metrics::DisplayUnit::WattHour => 3,
+    metrics::DisplayUnit::Joule => 7,
+    metrics::DisplayUnit::MilliWattHour | metrics::DisplayUnit::MilliAmpHour => 5,
+};
+// E=… の隣にジュールの列（J=0000000.00 J）も出すか（ビルド時の環境変数 SHOW_JOULES、既定 0）
+// DISPLAY_UNIT が Joule なら E=… がすでにジュールなので出さない
+const SHOW_JOULES: bool =
+    env_u32!("SHOW_JOULES", 0) != 0 && !matches!(DISPLAY_UNIT, metrics::DisplayUnit::Joule);
+// 容量 % の基準の電池: BATTERY の代表容量を周囲温度 AMBIENT_C で目減りさせた値（metrics::derate_capacity）
+// 温度センサはないので、寒い場所で測るときはここを書き換える（20 °C 以上なら目減りなし）
+// 電池を変えるときは BATTERY だけを書き換える（1分集計と累計の % はどちらも BATTERY_CAPACITY_UWS から同じ式で出す）
+const BATTERY: metrics::BatteryType = metrics::BatteryType::NimhAa; // 単三 2.5 Wh
+const BATTERY_CAPACITY_WH: f32 = BATTERY.capacity_wh();
+const BATTERY_CAPACITY_UWS: u128 = (BATTERY_CAPACITY_WH as f64 * 3.6e9) as u128; // Wh → µW·s（2.5 Wh = 9e9）
+const _: () = core::assert!(
+    BATTERY_CAPACITY_UWS > 0,
+    "BATTERY capacity must be positive"
+);
+const AMBIENT_C: f32 = 20.0;
+// 主レールの電池の初期電荷 [mAh]（ビルド時の環境変数 INITIAL_CHARGE_MAH、既定 0 = 使わない）
+// 設定すると集計窓ごとにクーロンカウンタ式の残量（初期電荷 − 正味の放電電荷）を出す
+const INITIAL_CHARGE_MAH: u32 = env_u32!("INITIAL_CHARGE_MAH", 0);
+const COULOMB_SOC: bool = INITIAL_CHARGE_MAH != 0;
+// アラート出力（主レール、ヒステリシス付き）
+// 過電流: GPIO16 を High（ブザー/リレー用）。OC_TRIP_UA 以上で発報、OC_RELEASE_UA 以下で解除
+const OC_TRIP_UA: i32 = 1_500_000;
+const OC_RELEASE_UA: i32 = 1_400_000;
+// 低電圧: GPIO17 を Low（平常時 High）。UV_TRIP_MV 以下で発報、UV_RELEASE_MV 以上で解除
+const UV_TRIP_MV: i32 = 3_000;
+const UV_RELEASE_MV: i32 = 3_200;
+// 逆接続の検出: シャント電圧が -REVERSE_SHUNT_UV 以下のサンプルが REVERSE_SAMPLES 回続いたら VIN+/VIN- の入れ違いを疑う
+// （表示は負値を 0 に丸めるため、警告がないと「何も流れていない」ように見える）
+const REVERSE_SHUNT_UV: i32 = 100;
+const REVERSE_SAMPLES: u32 = 10;
+// 電流の符号の反転（ビルド時の環境変数 INVERT_CURRENT、既定 0 = そのまま）
+// VIN+/VIN- を逆に配線したリグでも、放電（負荷へ流れる向き）を正として積算/統計/出力させる
+const INVERT_CURRENT: bool = env_u32!("INVERT_CURRENT", 0) != 0;
+// 同一バス上の INA219（アドレスは各ボードの半田ジャンパで変わります）
+// 既定は 0x44 の1台のみ。追加のレールはビルド時の環境変数 INA_ADDR_R1 / INA_ADDR_R2（例: 0x40）で有効にする（0 = なし）
+// 先頭（INA_ADDRS[0]）を主レールとして1分集計の対象にする
+const INA_ADDR_R0: u32 = env_u32!("INA_ADDR_R0", 0x44);
+const INA_ADDR_R1: u32 = env_u32!("INA_ADDR_R1", 0);
+const INA_ADDR_R2: u32 = env_u32!("INA_ADDR_R2", 0);
+const INA_COUNT: usize = if INA_ADDR_R2 != 0 {
+    3
+} else if INA_ADDR_R1 != 0 {
+    2
+} else {
+    1
+};
+const _: () = core::assert!(
+    INA_ADDR_R2 == 0 || INA_ADDR_R1 != 0,
+    "INA_ADDR_R2 requires INA_ADDR_R1"
+);
+const INA_ADDRS: [u8; INA_COUNT] = {
+    let all = [INA_ADDR_R0, INA_ADDR_R1, INA_ADDR_R2];
+    let mut addrs = [0u8; INA_COUNT];
+    let mut k = 0;
+    while k < INA_COUNT {
+        core::assert!(
+            all[k] >= 0x40 && all[k] <= 0x4F,
+            "INA219 address must be 0x40..=0x4F"
+        );
+        let mut j = 0;
+        while j < k {
+            core::assert!(all[j] != all[k], "duplicate INA219 address");
+            j += 1;
+        }
+        addrs[k] = all[k] as u8;
+        k += 1;
+    }
+    addrs
+};
+const RAIL_LABELS: [&str; INA_COUNT] = {
+    // defmt 出力用のレール名
+    let all = ["R0", "R1", "R2"];
+    let mut labels = [""; INA_COUNT];
+    let mut k = 0;
+    while k < INA_COUNT {
+        labels[k] = all[k];
+        k += 1;
+    }
+    labels
+};
+// 変換効率 P_out / P_in を1秒ごとに表示するレールの組（入力, 出力）
+// 既定は無効。ビルド時の環境変数 EFFICIENCY_IN / EFFICIENCY_OUT（レール番号、例: 0 と 1）を両方与えたときだけ有効
+const EFFICIENCY_RAILS: Option<(usize, usize)> =
+    match (option_env!("EFFICIENCY_IN"), option_env!("EFFICIENCY_OUT")) {
+        (Some(_), Some(_)) => Some((
+            env_u32!("EFFICIENCY_IN", 0) as usize,
+            env_u32!("EFFICIENCY_OUT", 0) as usize,
+        )),
+        _ => None,
+    };
+// レール番号として読めない値は `env_u32!` が変数名つきで、範囲外/同じレールはここでビルドエラーにする
+const _: () = {
+    if let Some((i, o)) = EFFICIENCY_RAILS {
+        core::assert!(
+            i < INA_COUNT && o < INA_COUNT,
+            "EFFICIENCY_IN / EFFICIENCY_OUT must be rail numbers of configured INA219s (0..INA_COUNT)"
+        );
+        core::assert!(
+            i != o,
+            "EFFICIENCY_IN and EFFICIENCY_OUT must be different rails"
+        );
+    }
+};
 
 #[entry]
 fn main() -> ! {
@@ -65,7 +437,7 @@ fn main() -> ! {
         &mut pac.RESETS,
     );
 
-    // I2C0 @ 100kHz（安定性重視）
+    // I2C0 @ I2C_FREQ_KHZ（既定 100kHz）
     // 外部プルアップ（4.7kΩ〜10kΩ）を前提。pull-up を有効化してから I2C 機能へ切り替える。
     let sda = pins
         .gpio4
@@ -75,361 +447,1403 @@ fn main() -> ! {
         .gpio5
         .into_pull_up_input()
         .into_function::<FunctionI2C>();
-    let i2c = I2C::i2c0(
+    let i2c = i2c0_bus(
         pac.I2C0,
         sda,
         scl,
-        // 100kHz 程度が無難。配線に問題がなければ 400kHz まで引き上げ可能。
-        100.kHz(),
         &mut pac.RESETS,
-        // system_clock 周波数を渡す（I2C タイミング計算に使用）。
-        // rp2040-hal の例と同様に system_clock を指定するのが正。
         clocks.system_clock.freq(),
     );
+    info!("I2C0: {=u32} kHz", I2C_FREQ_KHZ);
+
+    // 電力インジケータ LED（PWM）。0 mW で消灯、LED_MAX_POWER_MW 以上で全点灯
+    #[cfg(feature = "power-led")]
+    let mut led_pwm = {
+        let pwm_slices = hal::pwm::Slices::new(pac.PWM, &mut pac.RESETS);
+        let mut led_pwm = pwm_slices.pwm4;
+        led_pwm.set_ph_correct();
+        led_pwm.enable();
+        led_pwm.channel_b.output_to(pins.gpio25);
+        let _ = led_pwm.channel_b.set_duty_cycle_fully_off();
+        led_pwm
+    };
+
+    // アラート出力（過電流は High で発報、低電圧は Low で発報）
+    let mut oc_pin = pins
+        .gpio16
+        .into_push_pull_output_in_state(hal::gpio::PinState::Low);
+    let mut uv_pin = pins
+        .gpio17
+        .into_push_pull_output_in_state(hal::gpio::PinState::High);
+    // INA219 の電源（power-gate 時のみ。初期化に備えて入れておく）
+    #[cfg(feature = "power-gate")]
+    let mut ina_power = pins
+        .gpio18
+        .into_push_pull_output_in_state(hal::gpio::PinState::High);
+    let mut oc_alert = metrics::Hysteresis::above(OC_TRIP_UA, OC_RELEASE_UA);
+    let mut uv_alert = metrics::Hysteresis::below(UV_TRIP_MV, UV_RELEASE_MV);
+
+    // リセットボタン（立ち下がりエッジ割り込みで WFI スリープ中でも拾う）
+    let button: ButtonPin = pins.gpio15.into_pull_up_input();
+    button.set_interrupt_enabled(GpioInterrupt::EdgeLow, true);
+    cortex_m::interrupt::free(|cs| BUTTON.borrow(cs).replace(Some(button)));
+    // 安全：ハンドラ IO_IRQ_BANK0 は定義済みで、共有状態は Mutex 経由でのみ触る
+    unsafe { pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0) };
 
     // タイマ（Δt計測 & ウェイト）
     let mut timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+    // USB CDC シリアル（feature `usb-serial`）。ホストが列挙する時間も下の猶予で確保される
+    #[cfg(feature = "usb-serial")]
+    usb_serial::init(
+        pac.USBCTRL_REGS,
+        pac.USBCTRL_DPRAM,
+        clocks.usb_clock,
+        &mut pac.RESETS,
+    );
     // RTT アタッチ猶予（ホストが接続する時間を与える）
-    timer.delay_ms(500u32);
+    timer.delay_ms(STARTUP_DELAY_MS);
     info!("=== PICO INA219 MINIMAL ===");
     info!("Boot OK. Init INA219...");
+    // ビルド情報（複数台のログを見分け、どの設定のファームかを1行で残す。環境変数で上書きした値の確認用）
+    info!(
+        "build: v{=str} shunt={=u32} uOhm max={=u32} mA loop={=u32} ms addr={=[u8]:#x} current_LSB={=i64} uA/bit",
+        env!("CARGO_PKG_VERSION"), SHUNT_UOHM, MAX_EXPECTED_MA, LOOP_MS, INA_ADDRS[..], CURRENT_LSB_UA
+    );
 
-    // INA219 初期化（使用アドレスは `INA_ADDR`）
-    let mut ina = match init_ina219(i2c) {
-        Ok(device) => {
-            info!("INA219 init: OK");
-            device
+    // INA219 初期化（`INA_ADDRS` の各アドレス。I2C バスは RefCell で共有）
+    // バス復旧時にピンを取り外すため Option で保持する
+    let bus = RefCell::new(Some(i2c));
+    let mut devs = init_ina219_all(&bus);
+    let found = devs.iter().filter(|d| d.is_some()).count();
+    if found < INA_COUNT {
+        // 失敗時のみバスを走査して、実際に応答したアドレスを示す
+        if let Some(i2c) = bus.borrow_mut().as_mut() {
+            scan_i2c_bus(i2c);
         }
-        Err(_) => {
-            error!("INA219 init: NG - 配線/電源/アドレスを確認してください");
-            error!("Expected connections:");
-            error!("  VCC -> Pico 3V3");
-            error!("  GND -> Pico GND");
-            error!("  SDA -> Pico GPIO4");
-            error!("  SCL -> Pico GPIO5");
-            error!("  VIN+/VIN- -> measurement circuit");
-            error!("INA219 address: 0x{=u8:x} (default)", INA_ADDR);
-            core::panic!("INA219 init failed")
+    }
+    if found == 0 {
+        error!("INA219 init: NG - 配線/電源/アドレスを確認してください");
+        error!("Expected connections:");
+        error!("  VCC -> Pico 3V3");
+        error!("  GND -> Pico GND");
+        error!("  SDA -> Pico GPIO4");
+        error!("  SCL -> Pico GPIO5");
+        error!("  VIN+/VIN- -> measurement circuit");
+        for &addr in INA_ADDRS.iter() {
+            error!("INA219 address tried: 0x{=u8:x}", addr);
         }
-    };
+        core::panic!("INA219 init failed")
+    }
+    info!("INA219 init: {=usize}/{=usize} OK", found, INA_COUNT);
+    // 計測開始前のノイズフロア（電流 [mA] の統計）。この時点の負荷での値なので、無負荷で起動すると分解能の目安になる
+    // 電流を測らない（MEASURE_POWER = false）なら省く
+    for (k, slot) in devs.iter_mut().enumerate().filter(|_| MEASURE_POWER) {
+        let Some(dev) = slot.as_mut() else { continue };
+        let st = calibrate_noise_floor(dev, NOISE_SAMPLES, || timer.delay_ms(MIN_LOOP_MS));
+        if st.n > 0 {
+            info!("[{=str}] noise floor I[mA]: {}", RAIL_LABELS[k], st);
+        } else {
+            warn!("[{=str}] noise floor: no current samples", RAIL_LABELS[k]);
+        }
+    }
 
     // ループ（最小出力）
     info!("Start loop: print V/I/P every {=u32} ms", LOOP_MS);
-    // 積算用の基準時刻/前回時刻
-    let start = timer.get_counter();
+    // 現在の計測周期 [ms]（コマンド/待機中の引き延ばしで変更される）
+    let mut loop_ms: u32 = LOOP_MS;
+    let mut adaptive = metrics::AdaptivePeriod::new(LOOP_MS, MAX_LOOP_MS, IDLE_QUIET_SAMPLES);
+    // 周期待ちは WFI スリープ（ALARM0 の割り込みで起床）。ビジーウェイトより Pico 自身の消費が減る
+    let mut alarm = timer.alarm_0().unwrap();
+    alarm.enable_interrupt();
+    cortex_m::interrupt::free(|cs| ALARM.borrow(cs).replace(Some(alarm)));
+    // 安全：ハンドラ TIMER_IRQ_0 は定義済みで、共有状態は Mutex 経由でのみ触る
+    unsafe { pac::NVIC::unmask(pac::Interrupt::TIMER_IRQ_0) };
+
+    if OUTPUT_CSV {
+        println!(
+            "{=str}",
+            if CSV_FIXED_RATE {
+                csv::FIXED_HEADER
+            } else {
+                csv::HEADER
+            }
+        );
+    }
+
+    // 最初の有効サンプルを待つ（WAIT_FIRST_SAMPLE_MS 指定時。ウォッチドッグ開始前なので長く待ってもリセットされない）
+    // 見つかればそのサンプルを積算の初期値にし、時間切れなら従来どおり直後から計測する
+    let first_sample = match devs[0].as_mut() {
+        Some(dev) if WAIT_FIRST => {
+            info!(
+                "Waiting for first nonzero sample (up to {=u32} ms)...",
+                WAIT_FIRST_SAMPLE_MS
+            );
+            let m = wait_first_sample(dev, WAIT_FIRST_SAMPLE_MS / MIN_LOOP_MS.max(1), || {
+                timer.delay_ms(MIN_LOOP_MS)
+            });
+            if m.is_none() {
+                warn!(
+                    "No nonzero sample within {=u32} ms, starting anyway",
+                    WAIT_FIRST_SAMPLE_MS
+                );
+            }
+            m
+        }
+        _ => None,
+    };
+
+    // ウォッチドッグ開始（デバッガで停止中はカウントしない）。以降は毎周の読み出し後にフィード
+    watchdog.pause_on_debug(true);
+    watchdog.start(fugit::MicrosDurationU32::millis(WATCHDOG_MS));
+    info!("Watchdog: {=u32} ms", WATCHDOG_MS);
+
+    // 積算用の基準時刻/前回時刻（リセットボタンで現在時刻に戻す）
+    let mut start = timer.get_counter();
+    // 起動基準（リセットボタンでも戻さない。壁時計用）
+    let boot = start;
+    // 次の計測境界（start から LOOP_MS 刻み。処理が周期を超えたら現在時刻から数え直す）
+    let mut next_wake = start;
     let mut last = start;
-    // 累積エネルギー（µW・ms）: last_p_uw * dt_ms を逐次加算
-    let mut energy_uWms: i64 = 0;
-    // 積算に用いる直近の電力（µW）。新しいサンプルが来る度に更新。
-    let mut last_p_uw: i64 = 0;
-    // 分ごとの集計（時間重み付き平均と消費エネルギー）
-    let mut last_ms_total: u64 = 0; // start を 0ms とする絶対経過msの前回値
-    let mut min_v_mV_ms: i64 = 0; // V[mV]×ms の積算
-    let mut min_i_uA_ms: i64 = 0; // I[µA]×ms の積算
-    let mut min_energy_uWms: i64 = 0; // P[µW]×ms の積算（その分だけ）
-    let mut min_duration_ms: u64 = 0; // その分に積算したms（理想は60,000）
-    let mut minute_count: u64 = 0; // 何分目（1始まり）
-    // 時間重み用に保持する直近の V/I（サンプル到来時に更新）
-    let mut last_v_mv: i32 = 0;
-    let mut last_i_ua: i32 = 0;
-    // 1秒ごと表示のための直近出力秒
-    let mut last_printed_sec: u64 = 0;
+    // 累積の電荷/エネルギー（レール別）: 直近サンプルの I/P を dt だけ矩形積算（カットオフは CURRENT_CUTOFF_MA / POWER_CUTOFF_MW、容量は BATTERY を温度補正）
+    let derate = metrics::derate_capacity(1.0, AMBIENT_C);
+    let capacity_uws = (BATTERY_CAPACITY_UWS as f64 * derate as f64) as u128;
+    info!(
+        "{=str} capacity: {=f32} Wh at {=f32} C",
+        BATTERY.label(),
+        BATTERY_CAPACITY_WH * derate,
+        AMBIENT_C
+    );
+    let mut acc: [metrics::Accumulators; INA_COUNT] = core::array::from_fn(|_| {
+        metrics::Accumulators::new(CURRENT_CUTOFF_MA)
+            .with_power_cutoff_mw(POWER_CUTOFF_MW)
+            .with_capacity_uws(capacity_uws)
+    });
+    acc[0].set_initial_charge_mah(INITIAL_CHARGE_MAH);
+    // 消費ペース [mWh/分]（レール別、EWMA で平滑化して1秒行に出す）
+    let mut rate: [metrics::EnergyRate; INA_COUNT] = [metrics::EnergyRate::new(0.2); INA_COUNT];
+    // 外れ値除去（レール別、電流で判定）
+    let mut outliers: [metrics::OutlierFilter<OUTLIER_WINDOW>; INA_COUNT] =
+        [metrics::OutlierFilter::new(OUTLIER_FACTOR as f32, OUTLIER_FLOOR_UA); INA_COUNT];
+    // 主レールの V[V]/I[mA]/P[mW] のサンプル統計（1分ごとに出力してリセット）
+    let mut minute_stats = [metrics::RunningStats::new(); 3];
+    // 主レールの最大需要電力（リセットボタンまで保持）
+    let mut demand = metrics::MaxDemand::<DEMAND_SAMPLES>::new();
+    // 試験終了の要約用（主レールの V[V]/I[mA] のサンプル統計。リセットボタンまで保持）
+    let mut test_stats = [metrics::RunningStats::new(); 2];
+    let mut end_of_test = metrics::IdleTrigger::new(END_IDLE_MS as u64);
+    // 積算に用いる直近の電力（µW、レール別）。新しいサンプルが来る度に更新。
+    let mut last_p_uw: [i64; INA_COUNT] = [0; INA_COUNT];
+    // last_p_uw を積算に使ってよい期限（start からの ms、レール別）。サンプル到来時に延長
+    let mut fresh_until_ms: [u64; INA_COUNT] = [0; INA_COUNT];
+    // last_p_uw を積算に使ってよい区間の始まり（start からの ms、レール別）。最初のサンプルを読んだ時刻
+    let mut valid_from_ms: [u64; INA_COUNT] = [0; INA_COUNT];
+    // 前回までの稼働時間（ms、フラッシュから復元）。保存値は これ + 今回の経過
+    let mut uptime_base_ms: u64 = 0;
+    // 集計窓ごとの集計（主レールのみ。時間重み付き平均と消費エネルギー、start を 0ms とする）
+    let mut minute_agg = metrics::MinuteAggregator::new().with_window_ms(AGG_WINDOW_MS as u64);
+    // 積算と時間重み用に保持する直近の V/I（レール別、サンプル到来時に更新）
+    let mut last_v_mv: [i32; INA_COUNT] = [0; INA_COUNT];
+    let mut last_i_ua: [i32; INA_COUNT] = [0; INA_COUNT];
+    // 待って得た最初のサンプルで主レールの直近値を埋め、t=0 から実データで積算する
+    if let Some(m) = first_sample {
+        last_v_mv[0] = m.bus_mv;
+        last_i_ua[0] = m.current_ua.unwrap_or(0);
+        last_p_uw[0] = m.power_uw.unwrap_or(0) as i64;
+        if m.current_ua.is_some() && m.power_uw.is_some() {
+            fresh_until_ms[0] = if POWER_GATE {
+                GATE_ON_MS
+            } else {
+                SAMPLE_HOLD_PERIODS * LOOP_MS as u64
+            };
+        }
+        info!("First sample: {}", m);
+    }
+    // フラッシュに保存済みの累計値があれば主レールの積算を引き継ぐ（空/破損ならゼロから）
+    let (mut persist_store, restored) = persist::Store::open();
+    match restored {
+        Some(t) => {
+            info!(
+                "Persist: restored E={=i64} uWs Q={=i64} uAs uptime={=u64} ms",
+                t.energy_uws, t.charge_uas, t.uptime_ms
+            );
+            // 保存時に i128/u128 の積算値を i64 へ飽和させているので、i64 の範囲を超えていた分は戻らない
+            acc[0].restore(t.charge_uas, t.energy_uws.max(0) as u128);
+            uptime_base_ms = t.uptime_ms;
+        }
+        None => info!("Persist: no valid record, starting from zero"),
+    }
+    // 直近にフラッシュへ保存した時刻（start からの ms）
+    let mut last_persist_ms: u64 = 0;
+    // 連続読み出し失敗回数（レール別）。I2C_RECOVERY_ERRORS でバス復旧
+    let mut consecutive_errors: [u32; INA_COUNT] = [0; INA_COUNT];
+    // 切断判定用の連続失敗回数（レール別）。バス復旧ではリセットしない
+    let mut fail_streak: [u32; INA_COUNT] = [0; INA_COUNT];
+    // 読み出し結果の内訳（レール別、リセットボタンまで累計）。集計窓ごとに収率を出す
+    let mut sample_counts = [metrics::SampleCounters::new(); INA_COUNT];
+    // 切断中のレール（devs から外し、INA_REDETECT_MS ごとに再検出する）
+    let mut disconnected: [bool; INA_COUNT] = [false; INA_COUNT];
+    // 直近の計測値（レール別）。切断と判定したときに経緯としてログへ出す
+    let mut recent: [SampleHistory<Measurement, RECENT_SAMPLES>; INA_COUNT] =
+        [SampleHistory::filled(Measurement::ZERO); INA_COUNT];
+    let mut last_redetect_ms: u64 = 0;
+    // 直近サンプルの演算オーバーフロー状態（レール別、遷移時のみ警告）
+    let mut overflowed: [bool; INA_COUNT] = [false; INA_COUNT];
+    let mut reversed = [metrics::Sustained::new(REVERSE_SAMPLES); INA_COUNT];
+    // フルスケール接近の警告を出したか（レール別）
+    let mut near_fsr_warned: [bool; INA_COUNT] = [false; INA_COUNT];
+    // 累計の飽和を警告したか（レール別）
+    let mut saturation_warned: [bool; INA_COUNT] = [false; INA_COUNT];
+    // 1秒ごと表示のための直近出力秒（レール別）
+    let mut last_printed_sec: [u64; INA_COUNT] = [0; INA_COUNT];
+    let mut last_eff_sec: u64 = 0;
+    // 固定レートの CSV で繰り返す直前の値（レール別）
+    let mut csv_hold = [csv::HoldLast::<csv::Sample>::new(); INA_COUNT];
+    // 表示行の間引き用（レール別、V[mV]/Vsh[µV]/I[µA]/P[µW] のサンプル統計。1行出すたびにリセット）
+    let mut print_group = [[metrics::RunningStats::new(); 4]; INA_COUNT];
+    // 押しっぱなしで繰り返しリセットしないよう、離されるまで次の押下を受け付けない
+    let mut button_latched = false;
     loop {
         let now = timer.get_counter();
         // 経過時間と微小区間 dt（ms）を取得
-        let dt_ms_u64: u64 = (now - last).to_millis() as u64;
+        let raw_dt_ms: u64 = (now - last).to_millis() as u64;
+        let (dt_ms_u64, dt_clamped) =
+            metrics::clamp_dt_ms(raw_dt_ms, MAX_DT_PERIODS * loop_ms as u64);
+        if dt_clamped {
+            warn!(
+                "dt {=u64} ms out of range, clamped to {=u64} ms",
+                raw_dt_ms, dt_ms_u64
+            );
+        }
         last = now; // 次回用に更新
-        // エネルギー積算（µW・ms）。矩形近似で last_p_uw を使用。
-        energy_uWms = energy_uWms.saturating_add(last_p_uw.saturating_mul(dt_ms_u64 as i64));
-        // 総経過時間（ms）
+                    // 総経過時間（ms）
         let elapsed_ms_total: u64 = (now - start).to_millis() as u64;
+        let prev_ms_total = elapsed_ms_total.saturating_sub(dt_ms_u64);
+        // 電荷/エネルギー積算。矩形近似で直近サンプルの I/P を使用。
+        // 読み出しが途切れた区間（fresh_until_ms 以降）や切断中のレールは、古い値で水増ししないよう積算しない
+        for k in 0..INA_COUNT {
+            if devs[k].is_none() {
+                continue;
+            }
+            let held = metrics::held_ms_from(
+                prev_ms_total,
+                elapsed_ms_total,
+                valid_from_ms[k],
+                fresh_until_ms[k],
+            );
+            if held == 0 {
+                continue;
+            }
+            acc[k].update(
+                last_v_mv[k] as f32 / 1000.0,
+                last_i_ua[k] as f32 / 1000.0,
+                last_p_uw[k] as f32 / 1000.0,
+                held as u32,
+            );
+        }
+        // 累計が上限で頭打ちになったら1回だけ知らせる（黙って増えなくなるのを避ける）
+        for (k, a) in acc.iter().enumerate() {
+            if a.saturated() && !saturation_warned[k] {
+                warn!(
+                    "[{=str}] energy/charge totals saturated, long-run totals unreliable",
+                    RAIL_LABELS[k]
+                );
+                saturation_warned[k] = true;
+            }
+        }
+        let primary_online = devs[0].is_some();
         let curr_sec: u64 = elapsed_ms_total / 1000;
 
-        // 1分区切りの時間重み付き積算（分境界をまたぐ場合は分割）
-        let mut remain = elapsed_ms_total.saturating_sub(last_ms_total);
-        while remain > 0 {
-            let this_min = last_ms_total / 60_000;
-            let next_boundary = (this_min + 1) * 60_000;
-            let step = core::cmp::min(remain, next_boundary - last_ms_total);
-            let step_i64 = step as i64;
-
-            // 分の積算（矩形近似）
-            min_energy_uWms = min_energy_uWms.saturating_add(last_p_uw.saturating_mul(step_i64));
-            min_v_mV_ms = min_v_mV_ms
-                .saturating_add((last_v_mv as i64).saturating_mul(step_i64));
-            min_i_uA_ms = min_i_uA_ms
-                .saturating_add((last_i_ua as i64).saturating_mul(step_i64));
-            min_duration_ms = min_duration_ms.saturating_add(step);
-
-            last_ms_total = last_ms_total.saturating_add(step);
-            remain -= step;
-
-            // 分境界に到達したら出力してリセット
-            if last_ms_total % 60_000 == 0 {
-                minute_count = minute_count.saturating_add(1);
-
-                // 平均 V/I を算出（整数で切り捨て）
-                let avg_v_mv: i32 = if min_duration_ms > 0 {
-                    (min_v_mV_ms / min_duration_ms as i64) as i32
-                } else {
-                    0
-                };
-                let avg_i_ua: i32 = if min_duration_ms > 0 {
-                    (min_i_uA_ms / min_duration_ms as i64) as i32
-                } else {
-                    0
-                };
-
-                // V: 2桁整数＋3桁小数（固定幅）
-                let v_mv_u32: u32 = if avg_v_mv > 0 { avg_v_mv as u32 } else { 0 };
-                let v_int_raw = v_mv_u32 / 1000;
-                let v_int = core::cmp::min(v_int_raw, 99);
-                let v_frac = v_mv_u32 % 1000;
-                let v_i_t = ((v_int / 10) % 10) as u8;
-                let v_i_o = (v_int % 10) as u8;
-                let v_f_h = ((v_frac / 100) % 10) as u8;
-                let v_f_t = ((v_frac / 10) % 10) as u8;
-                let v_f_o = (v_frac % 10) as u8;
-
-                // I: 4桁整数＋1桁小数（固定幅）
-                let i_ua_u32: u32 = if avg_i_ua > 0 { avg_i_ua as u32 } else { 0 };
-                let i_mAx10 = i_ua_u32 / 100; // mA×10
-                let i_int_raw = i_mAx10 / 10;
-                let i_int = core::cmp::min(i_int_raw, 9_999);
-                let i_frac1 = i_mAx10 % 10;
-                let i_i_th = ((i_int / 1000) % 10) as u8;
-                let i_i_h = ((i_int / 100) % 10) as u8;
-                let i_i_t = ((i_int / 10) % 10) as u8;
-                let i_i_o = (i_int % 10) as u8;
-                let i_f_o = i_frac1 as u8;
-
-                // その1分で消費した電池 %（小数2桁、切り捨て、最大 999.99）
-                let e_pos: u128 = if min_energy_uWms > 0 { min_energy_uWms as u128 } else { 0 };
-                let pct_x100: u128 = e_pos
-                    .saturating_mul(10_000) // 100×100
-                    / 9_000_000_000_000u128; // 2.5Wh を µW・ms に換算
-                let pct_int_raw: u64 = (pct_x100 / 100) as u64;
-                let pct_int = core::cmp::min(pct_int_raw, 999);
-                let pct_frac2: u8 = (pct_x100 % 100) as u8;
-                let pct_d3 = ((pct_int / 100) % 10) as u8;
-                let pct_d2 = ((pct_int / 10) % 10) as u8;
-                let pct_d1 = (pct_int % 10) as u8;
-                let pct_f1: char = (b'0' + (pct_frac2 / 10)) as char;
-                let pct_f2: char = (b'0' + (pct_frac2 % 10)) as char;
-
-                // 何分目（2桁）
-                let min_cap = core::cmp::min(minute_count, 99);
-                let mn_t = ((min_cap / 10) % 10) as u8;
-                let mn_o = (min_cap % 10) as u8;
+        // 集計窓区切りの時間重み付き積算（主レール。窓の境界をまたぐ場合は分割）
+        // 直近サンプルが有効な部分だけを積算し、主レールの切断中は時間だけ進める
+        minute_agg.set_valid_until(if primary_online { fresh_until_ms[0] } else { 0 });
+        minute_agg.set_valid_from(valid_from_ms[0]);
+        while let Some(report) =
+            minute_agg.advance(elapsed_ms_total, last_v_mv[0], last_i_ua[0], last_p_uw[0])
+        {
+            // その窓で消費した電池 %（小数2桁、四捨五入、最大 999.99）。累計の % と同じ容量・同じ式
+            let e_pos_uws: u128 = if report.energy_uwms > 0 {
+                report.energy_uwms as u128 / 1000
+            } else {
+                0
+            }; // µW·ms → µW·s
+            let pct_x100 =
+                fmt::round_scaled(metrics::percent_of_capacity(e_pos_uws, capacity_uws), 2);
+            if report.saturated {
+                warn!(
+                    "{=u64}{=str}: window totals saturated, average/energy unreliable",
+                    report.index, AGG_INDEX_LABEL
+                );
+            }
 
+            if OUTPUT_HUMAN {
+                let (mut mn_buf, mut v_buf, mut i_buf, mut pct_buf) =
+                    ([0u8; 4], [0u8; 8], [0u8; 12], [0u8; 8]);
                 info!(
-                    "{=char}{=char}分目  平均: V={=char}{=char}.{=char}{=char}{=char} V  I={=char}{=char}{=char}{=char}.{=char} mA  |  1分消費: AA={=char}{=char}{=char}.{=char}{=char}%",
-                    (b'0' + mn_t) as char, (b'0' + mn_o) as char,
-                    // V
-                    (b'0' + v_i_t) as char, (b'0' + v_i_o) as char,
-                    (b'0' + v_f_h) as char, (b'0' + v_f_t) as char,
-                    (b'0' + v_f_o) as char,
-                    // I
-                    (b'0' + i_i_th) as char, (b'0' + i_i_h) as char,
-                    (b'0' + i_i_t) as char, (b'0' + i_i_o) as char,
-                    (b'0' + i_f_o) as char,
-                    // %
-                    (b'0' + pct_d3) as char, (b'0' + pct_d2) as char,
-                    (b'0' + pct_d1) as char, pct_f1, pct_f2
+                    "{=str}{=str}  平均: V={=str} V  I={=str} mA  |  {=str}: {=str}={=str}%",
+                    fmt::fmt_fixed(report.index.min(99) as u32, 2, 0, &mut mn_buf),
+                    AGG_INDEX_LABEL,
+                    fmt::fmt_fixed(report.avg_v_mv.max(0) as u32, 2, 3, &mut v_buf),
+                    fmt::fmt_fixed(
+                        report.avg_i_ua.max(0) as u32 / 100,
+                        I_INT_DIGITS,
+                        1,
+                        &mut i_buf
+                    ), // mA×10
+                    AGG_USAGE_LABEL,
+                    BATTERY.label(),
+                    fmt::fmt_fixed(pct_x100, 3, 2, &mut pct_buf)
                 );
-
-                // リセット（次の1分へ）
-                min_v_mV_ms = 0;
-                min_i_uA_ms = 0;
-                min_energy_uWms = 0;
-                min_duration_ms = 0;
+            }
+            if OUTPUT_HUMAN && VERBOSITY >= Verbosity::Summary {
+                // その窓のサンプル統計（時間重みなし）
+                for (label, st) in ["V[V]", "I[mA]", "P[mW]"].iter().zip(minute_stats.iter()) {
+                    if st.n == 0 {
+                        continue;
+                    }
+                    info!(
+                        "  {=str}: {}  (min @{=u64} ms, max @{=u64} ms)",
+                        label,
+                        st,
+                        st.min_at_ms(),
+                        st.max_at_ms()
+                    );
+                }
+                info!(
+                    "  demand max ({=u32} s avg): {=f32} mW",
+                    DEMAND_WINDOW_S,
+                    demand.peak_demand_mw()
+                );
+                for (k, c) in sample_counts.iter().enumerate() {
+                    if c.attempts == 0 {
+                        continue;
+                    }
+                    info!(
+                        "  [{=str}] sample yield: {=f32}% ({=u64} ok / {=u64} none / {=u64} err of {=u64})",
+                        RAIL_LABELS[k], c.yield_percent(), c.measurements, c.none, c.errors, c.attempts
+                    );
+                }
+                if COULOMB_SOC {
+                    info!(
+                        "  SoC (coulomb): {=f32} mAh left of {=u32} mAh ({=f32}%)",
+                        acc[0].remaining_mah(),
+                        INITIAL_CHARGE_MAH,
+                        acc[0].soc_coulomb_percent()
+                    );
+                }
+            }
+            if OUTPUT_MINUTE_CRC {
+                let mut row = [0u8; csv::MINUTE_ROW_MAX];
+                let n = csv::format_minute_row(
+                    report.index,
+                    report.avg_v_mv,
+                    report.avg_i_ua,
+                    report.energy_uwms,
+                    report.covered_ms,
+                    &mut row,
+                );
+                println!("{=str}", core::str::from_utf8(&row[..n]).unwrap_or(""));
+            }
+            for st in minute_stats.iter_mut() {
+                st.reset();
             }
         }
 
-        match ina_next(&mut ina) {
-            Ok(Some((v_mv, i_ua, p_uw))) => {
-                // 単位変換は整数ベースで行い、出力は固定幅・ゼロ埋めで桁をそろえる
-                // 電圧: v_mv [mV] -> 00.000 V（2桁整数＋3桁小数）
-                let v_mv_u32: u32 = if v_mv > 0 { v_mv as u32 } else { 0 };
-                let v_int: u32 = v_mv_u32 / 1000; // 0..32
-                let v_frac: u32 = v_mv_u32 % 1000; // 0..999
-                let v_i_t = ((v_int / 10) % 10) as u8;
-                let v_i_o = (v_int % 10) as u8;
-                let v_f_h = ((v_frac / 100) % 10) as u8;
-                let v_f_t = ((v_frac / 10) % 10) as u8;
-                let v_f_o = (v_frac % 10) as u8;
-
-                // 電流: i_ua [µA] -> 0000.0 mA（4桁整数＋1桁小数）
-                let i_ua_u32: u32 = if i_ua > 0 { i_ua as u32 } else { 0 };
-                let i_max10: u32 = i_ua_u32 / 100; // mA×10
-                let i_int: u32 = i_max10 / 10; // 0..9999
-                let i_frac1: u32 = i_max10 % 10; // 0..9
-                let i_i_th = ((i_int / 1000) % 10) as u8;
-                let i_i_h  = ((i_int / 100) % 10) as u8;
-                let i_i_t  = ((i_int / 10) % 10) as u8;
-                let i_i_o  = (i_int % 10) as u8;
-                let i_f_o  = i_frac1 as u8;
-
-                // 電力: p_uw [µW] -> 00000.0 mW（5桁整数＋1桁小数）
-                let p_uw_u32: u32 = if p_uw > 0 { p_uw as u32 } else { 0 };
-                let p_mwx10: u32 = p_uw_u32 / 100; // mW×10
-                let p_int: u32 = p_mwx10 / 10; // 0..99999
-                let p_frac1: u32 = p_mwx10 % 10; // 0..9
-                let p_i_tth = ((p_int / 10_000) % 10) as u8;
-                let p_i_th  = ((p_int / 1000) % 10) as u8;
-                let p_i_h   = ((p_int / 100) % 10) as u8;
-                let p_i_t   = ((p_int / 10) % 10) as u8;
-                let p_i_o   = (p_int % 10) as u8;
-                let p_f_o   = p_frac1 as u8;
-
-                // 積算用の現在電力（µW）と V/I（時間重み用）を更新
-                last_p_uw = p_uw as i64;
-                last_v_mv = v_mv as i32;
-                last_i_ua = i_ua as i32;
-                // 表示は「1秒ごと、整数秒」。その秒にデータが取得できなければ出力しない。
-                if curr_sec > last_printed_sec && curr_sec > 0 {
-                    // 表示用の値を整数演算で作る（切り捨て）。負値は0として扱う。
-                    let e_uWms_pos: u128 = if energy_uWms > 0 { energy_uWms as u128 } else { 0 };
-                    // mWh（×100, 切り捨て）: µW・ms / 3.6e9
-                    let mwh_x100: u128 = e_uWms_pos.saturating_mul(100)
-                        / 3_600_000_000u128;
-                    let mwh_int: u64 = (mwh_x100 / 100) as u64;
-                    let mwh_frac2: u8 = (mwh_x100 % 100) as u8;
-                    let mwh_int_cap = core::cmp::min(mwh_int, 99_999);
-                    let mwh_d5 = ((mwh_int_cap / 10_000) % 10) as u8;
-                    let mwh_d4 = ((mwh_int_cap / 1000) % 10) as u8;
-                    let mwh_d3 = ((mwh_int_cap / 100) % 10) as u8;
-                    let mwh_d2 = ((mwh_int_cap / 10) % 10) as u8;
-                    let mwh_d1 = (mwh_int_cap % 10) as u8;
-                    let mwh_f1: char = (b'0' + (mwh_frac2 / 10)) as char;
-                    let mwh_f2: char = (b'0' + (mwh_frac2 % 10)) as char;
-
-                    // Wh（×100, 切り捨て）: µW・ms / 3.6e12
-                    let wh_x100: u128 = e_uWms_pos.saturating_mul(100)
-                        / 3_600_000_000_000u128;
-                    let wh_int: u64 = (wh_x100 / 100) as u64;
-                    let wh_frac2: u8 = (wh_x100 % 100) as u8;
-                    let wh_int_cap = core::cmp::min(wh_int, 999);
-                    let wh_d3 = ((wh_int_cap / 100) % 10) as u8;
-                    let wh_d2 = ((wh_int_cap / 10) % 10) as u8;
-                    let wh_d1 = (wh_int_cap % 10) as u8;
-                    let wh_f1: char = (b'0' + (wh_frac2 / 10)) as char;
-                    let wh_f2: char = (b'0' + (wh_frac2 % 10)) as char;
-
-                    // 単三電池（2.5 Wh = 9e12 µW・ms）に対する割合（% ×100, 切り捨て）
-                    let aa_cap_uWms: u128 = 9_000_000_000_000u128;
-                    let pct_x100: u128 = e_uWms_pos
-                        .saturating_mul(10_000) // 100% × 100（小数2桁）
-                        / aa_cap_uWms;
-                    let pct_int: u64 = (pct_x100 / 100) as u64;
-                    let pct_frac2: u8 = (pct_x100 % 100) as u8;
-                    let pct_int_cap = core::cmp::min(pct_int, 999);
-                    let pct_d3 = ((pct_int_cap / 100) % 10) as u8;
-                    let pct_d2 = ((pct_int_cap / 10) % 10) as u8;
-                    let pct_d1 = (pct_int_cap % 10) as u8;
-                    let pct_f1: char = (b'0' + (pct_frac2 / 10)) as char;
-                    let pct_f2: char = (b'0' + (pct_frac2 % 10)) as char;
-
-                    // 時間（00時間00分00秒）— 2桁固定
-                    let h_total = core::cmp::min(curr_sec / 3600, 99);
-                    let m_total = (curr_sec % 3600) / 60;
-                    let s_total = curr_sec % 60;
-                    let h_t = ((h_total / 10) % 10) as u8;
-                    let h_o = (h_total % 10) as u8;
-                    let m_t = ((m_total / 10) % 10) as u8;
-                    let m_o = (m_total % 10) as u8;
-                    let s_t = ((s_total / 10) % 10) as u8;
-                    let s_o = (s_total % 10) as u8;
+        // 電源を入れ、消えた校正/設定を書き直してから最初の変換が終わるまで待つ
+        #[cfg(feature = "power-gate")]
+        {
+            let _ = ina_power.set_high();
+            timer.delay_ms(INA_POWER_UP_MS);
+            for (k, slot) in devs.iter_mut().enumerate() {
+                let Some(dev) = slot.as_mut() else { continue };
+                if reconfigure_ina219(dev).is_err() {
+                    warn!(
+                        "[{=str}] INA219 re-init after power-up failed",
+                        RAIL_LABELS[k]
+                    );
+                }
+            }
+            timer.delay_ms(MIN_LOOP_MS + 1);
+        }
 
+        // 切断中のレールを再検出（見つかれば次の読み出しから再開）
+        if disconnected.iter().any(|&d| d)
+            && elapsed_ms_total.saturating_sub(last_redetect_ms) >= INA_REDETECT_MS
+        {
+            last_redetect_ms = elapsed_ms_total;
+            for k in 0..INA_COUNT {
+                if !disconnected[k] {
+                    continue;
+                }
+                if let Ok(dev) = init_ina219(SharedI2c(&bus), INA_ADDRS[k]) {
                     info!(
-                        "{=char}{=char}時間{=char}{=char}分{=char}{=char}秒  E={=char}{=char}{=char}{=char}{=char}.{=char}{=char} mWh ({=char}{=char}{=char}.{=char}{=char} Wh)  |  V={=char}{=char}.{=char}{=char}{=char} V  I={=char}{=char}{=char}{=char}.{=char} mA  P={=char}{=char}{=char}{=char}{=char}.{=char} mW  |  AA={=char}{=char}{=char}.{=char}{=char}%",
-                        // 時刻 HH:MM:SS
-                        (b'0' + h_t) as char, (b'0' + h_o) as char,
-                        (b'0' + m_t) as char, (b'0' + m_o) as char,
-                        (b'0' + s_t) as char, (b'0' + s_o) as char,
-                        // E mWh 5桁.2桁
-                        (b'0' + mwh_d5) as char, (b'0' + mwh_d4) as char,
-                        (b'0' + mwh_d3) as char, (b'0' + mwh_d2) as char,
-                        (b'0' + mwh_d1) as char, mwh_f1, mwh_f2,
-                        // E Wh 3桁.2桁
-                        (b'0' + wh_d3) as char, (b'0' + wh_d2) as char,
-                        (b'0' + wh_d1) as char, wh_f1, wh_f2,
-                        // V 2桁.3桁
-                        (b'0' + v_i_t) as char, (b'0' + v_i_o) as char,
-                        (b'0' + v_f_h) as char, (b'0' + v_f_t) as char,
-                        (b'0' + v_f_o) as char,
-                        // I 4桁.1桁
-                        (b'0' + i_i_th) as char, (b'0' + i_i_h) as char,
-                        (b'0' + i_i_t) as char, (b'0' + i_i_o) as char,
-                        (b'0' + i_f_o) as char,
-                        // P 5桁.1桁
-                        (b'0' + p_i_tth) as char, (b'0' + p_i_th) as char,
-                        (b'0' + p_i_h) as char, (b'0' + p_i_t) as char,
-                        (b'0' + p_i_o) as char, (b'0' + p_f_o) as char,
-                        // AA % 3桁.2桁
-                        (b'0' + pct_d3) as char, (b'0' + pct_d2) as char,
-                        (b'0' + pct_d1) as char, pct_f1, pct_f2
+                        "[{=str}] INA219 reconnected at 0x{=u8:x}, resuming",
+                        RAIL_LABELS[k], INA_ADDRS[k]
+                    );
+                    devs[k] = Some(dev);
+                    disconnected[k] = false;
+                    fail_streak[k] = 0;
+                    outliers[k].reset();
+                    consecutive_errors[k] = 0;
+                }
+            }
+        }
+
+        // 初期化できたレールを順に読む
+        let mut primary_active: Option<bool> = None; // 主レールに新しい電流値が来たら、閾値以上か
+        let mut csv_fresh: [Option<csv::Sample>; INA_COUNT] = [None; INA_COUNT]; // 今周に読めた値（固定レートの CSV 用）
+        for (k, slot) in devs.iter_mut().enumerate() {
+            let Some(dev) = slot.as_mut() else { continue };
+            let (res, retries) = with_retry(
+                INA_READ_ATTEMPTS,
+                |n| timer.delay_ms(INA_RETRY_BASE_MS << n),
+                || ina_next(dev).map(|m| m.map(normalize_sign)),
+            );
+            if retries > 0 && res.is_ok() {
+                info!(
+                    "[{=str}] read OK after {=u32} retries",
+                    RAIL_LABELS[k], retries
+                );
+            }
+            if res.is_ok() {
+                consecutive_errors[k] = 0;
+                fail_streak[k] = 0;
+            }
+            // 収率は外れ値の除去前に数える（I2C と変換タイミングの診断用）
+            match res {
+                Ok(Some(_)) => sample_counts[k].record_measurement(),
+                Ok(None) => sample_counts[k].record_none(),
+                Err(_) => sample_counts[k].record_error(),
+            }
+            // 外れ値は新データなしと同じ扱い（直前の値で積算を続け、統計/出力にも入れない）
+            let res = match res {
+                Ok(Some(m))
+                    if m.current_ua
+                        .is_some_and(|i_ua| outliers[k].reject(i_ua as f32)) =>
+                {
+                    warn!(
+                        "[{=str}] outlier dropped: I={=i32} uA P={=i32} uW",
+                        RAIL_LABELS[k],
+                        m.current_ua.unwrap_or(0),
+                        m.power_uw.unwrap_or(0)
+                    );
+                    Ok(None)
+                }
+                other => other,
+            };
+            match res {
+                Ok(Some(m)) => {
+                    recent[k].push(m);
+                    let Measurement {
+                        bus_mv: v_mv,
+                        shunt_uv,
+                        current_ua: i_ua,
+                        power_uw: p_uw,
+                        overflow,
+                        ..
+                    } = m;
+                    // 演算オーバーフロー（current_LSB が負荷に対して小さすぎる）は遷移時に記録
+                    if overflow != overflowed[k] {
+                        if overflow {
+                            warn!("[{=str}] INA219 math overflow: current_LSB too small for load (I/P from shunt)", RAIL_LABELS[k]);
+                        } else {
+                            info!("[{=str}] INA219 math overflow cleared", RAIL_LABELS[k]);
+                        }
+                        overflowed[k] = overflow;
+                    }
+                    // フルスケール接近（レール別に1回だけ。リセットボタンで再び警告する）
+                    if !near_fsr_warned[k] {
+                        if let Some(what) = near_full_scale(v_mv, shunt_uv) {
+                            warn!(
+                                "[{=str}] {=str} approaching full-scale (V={=i32} mV Vsh={=i32} uV), consider range/shunt change",
+                                RAIL_LABELS[k], what, v_mv, shunt_uv
+                            );
+                            near_fsr_warned[k] = true;
+                        }
+                    }
+                    if let Some(on) = reversed[k].update(shunt_uv <= -REVERSE_SHUNT_UV) {
+                        if on {
+                            warn!("[{=str}] sustained negative shunt voltage ({=i32} uV): check VIN+/VIN- polarity", RAIL_LABELS[k], shunt_uv);
+                        } else {
+                            info!(
+                                "[{=str}] shunt voltage back to non-negative",
+                                RAIL_LABELS[k]
+                            );
+                        }
+                    }
+                    // 積算用の現在電力（µW）と主レールの V/I（時間重み用）を更新
+                    // 未校正（電流/電力なし）のときは有効期限を延ばさず、積算を止める
+                    last_p_uw[k] = p_uw.unwrap_or(0) as i64;
+                    last_v_mv[k] = v_mv;
+                    last_i_ua[k] = i_ua.unwrap_or(0);
+                    if i_ua.is_some() && p_uw.is_some() {
+                        // power-gate 時は電源を入れていた間だけ（切っている間は 0 として積算しない）
+                        let hold_ms = if POWER_GATE {
+                            GATE_ON_MS
+                        } else {
+                            SAMPLE_HOLD_PERIODS * loop_ms as u64
+                        };
+                        // 起動直後/途切れた後の最初のサンプルは、読み出した時刻を積算の起点にする
+                        // （それより前の区間、例えば起動時の待ちに最初の実測値を掛けて水増ししない）
+                        // power-gate 時は毎周が電源投入からの計測窓なので、周の先頭からのまま
+                        if !POWER_GATE && fresh_until_ms[k] <= elapsed_ms_total {
+                            valid_from_ms[k] = (timer.get_counter() - start).to_millis() as u64;
+                        }
+                        fresh_until_ms[k] = elapsed_ms_total.saturating_add(hold_ms);
+                    }
+                    let mwh_min = p_uw.map(|p| rate[k].update(p as f32 / 1000.0));
+                    if k == 0 {
+                        if let (Some(i_ua), Some(th)) = (i_ua, IDLE_THRESHOLD_UA) {
+                            primary_active = Some(i_ua.unsigned_abs() >= th.get());
+                        }
+                        minute_stats[0].update_at(v_mv as f32 / 1000.0, elapsed_ms_total);
+                        test_stats[0].update(v_mv as f32 / 1000.0);
+                        if let (Some(i_ua), Some(p_uw)) = (i_ua, p_uw) {
+                            minute_stats[1].update_at(i_ua as f32 / 1000.0, elapsed_ms_total);
+                            minute_stats[2].update_at(p_uw as f32 / 1000.0, elapsed_ms_total);
+                            test_stats[1].update(i_ua as f32 / 1000.0);
+                            let busy = i_ua.unsigned_abs() >= END_IDLE_UA;
+                            if END_IDLE_MS > 0
+                                && end_of_test.update(busy, elapsed_ms_total)
+                                && OUTPUT_HUMAN
+                                && VERBOSITY >= Verbosity::Summary
+                            {
+                                log_test_summary(
+                                    &acc[0],
+                                    &test_stats,
+                                    end_of_test.idle_ms(elapsed_ms_total),
+                                );
+                            }
+                            demand.update(p_uw as f32 / 1000.0);
+                            // pct() は 0..=100 に飽和するので全点灯を超えることはない
+                            #[cfg(feature = "power-led")]
+                            {
+                                let duty = termviz::pct(p_uw as f32 / 1000.0, LED_MAX_POWER_MW);
+                                let _ = led_pwm.channel_b.set_duty_cycle_percent(duty);
+                            }
+
+                            // アラートは状態が変わったときだけピンを動かして記録する
+                            if let Some(on) = oc_alert.update(i_ua) {
+                                if on {
+                                    warn!(
+                                        "ALERT over-current: {=i32} uA >= {=i32} uA",
+                                        i_ua, OC_TRIP_UA
+                                    );
+                                    let _ = oc_pin.set_high();
+                                } else {
+                                    warn!("ALERT over-current cleared: {=i32} uA", i_ua);
+                                    let _ = oc_pin.set_low();
+                                }
+                            }
+                        }
+                        if let Some(on) = uv_alert.update(v_mv) {
+                            if on {
+                                warn!(
+                                    "ALERT under-voltage: {=i32} mV <= {=i32} mV",
+                                    v_mv, UV_TRIP_MV
+                                );
+                                let _ = uv_pin.set_low();
+                            } else {
+                                warn!("ALERT under-voltage cleared: {=i32} mV", v_mv);
+                                let _ = uv_pin.set_high();
+                            }
+                        }
+                    }
+                    csv_fresh[k] = Some(csv::Sample {
+                        v_mv,
+                        shunt_uv,
+                        i_ua,
+                        p_uw,
+                    });
+                    if OUTPUT_CSV && !CSV_FIXED_RATE {
+                        let mut row = [0u8; csv::ROW_MAX];
+                        let n = csv::format_row(
+                            RAIL_LABELS[k],
+                            elapsed_ms_total,
+                            v_mv,
+                            shunt_uv,
+                            i_ua,
+                            p_uw,
+                            acc[k].snapshot().1.min(i64::MAX as u128) as i64,
+                            &mut row,
+                        );
+                        println!("{=str}", core::str::from_utf8(&row[..n]).unwrap_or(""));
+                    }
+                    if OUTPUT_JSON {
+                        let mut line = [0u8; json::LINE_MAX];
+                        let n = json::format_sample(
+                            RAIL_LABELS[k],
+                            elapsed_ms_total,
+                            v_mv,
+                            i_ua,
+                            p_uw,
+                            acc[k].snapshot().1.min(i64::MAX as u128) as i64,
+                            &mut line,
+                        );
+                        println!("{=str}", core::str::from_utf8(&line[..n]).unwrap_or(""));
+                    }
+                    if OUTPUT_BINARY {
+                        let sample = frame::FrameSample {
+                            elapsed_ms: elapsed_ms_total as u32,
+                            v_mv: v_mv.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+                            i_ua: i_ua.unwrap_or(0),
+                            p_uw: p_uw.unwrap_or(0),
+                        };
+                        let mut buf = [0u8; frame::FRAME_LEN];
+                        let n = frame::encode_frame(&sample, &mut buf);
+                        println!("{=[u8]}", buf[..n]);
+                    }
+                    let group = &mut print_group[k];
+                    group[0].update(v_mv as f32);
+                    group[1].update(shunt_uv as f32);
+                    if let Some(i_ua) = i_ua {
+                        group[2].update(i_ua as f32);
+                    }
+                    if let Some(p_uw) = p_uw {
+                        group[3].update(p_uw as f32);
+                    }
+                    // 表示は「1秒ごと、整数秒」。その秒にデータが取得できなければ出力しない。
+                    // PRINT_EVERY 指定時は秒に関係なく PRINT_EVERY サンプルごと
+                    let print_due = if PRINT_DECIMATED {
+                        group[0].n >= PRINT_EVERY as u64
+                    } else {
+                        curr_sec > last_printed_sec[k] && curr_sec > 0
+                    };
+                    if OUTPUT_HUMAN && VERBOSITY >= Verbosity::Verbose {
+                        info!(
+                            "[{=str}] raw: Vsh_reg={=i16} (10uV) Vbus_reg={=u16} (4mV) I={=?} uA P={=?} uW",
+                            RAIL_LABELS[k], m.shunt_reg, m.bus_reg, i_ua, p_uw
+                        );
+                    }
+                    if OUTPUT_HUMAN && VERBOSITY >= Verbosity::Normal && print_due {
+                        // 間引き時はグループ平均を表示（I/P はサンプルがなければ未校正と同じ扱い）
+                        let (v_mv, shunt_uv, i_ua, p_uw) = if PRINT_DECIMATED {
+                            let mean =
+                                |st: &metrics::RunningStats| (st.n > 0).then_some(st.mean as i32);
+                            (
+                                group[0].mean as i32,
+                                group[1].mean as i32,
+                                mean(&group[2]),
+                                mean(&group[3]),
+                            )
+                        } else {
+                            (v_mv, shunt_uv, i_ua, p_uw)
+                        };
+                        // 表示用の値は積算器の読み出しを小数2桁の整数にして作る（四捨五入）。負値は0として扱う
+                        let total = acc[k].readout_in(DISPLAY_UNIT);
+                        let mut bufs = [[0u8; 12]; 13];
+                        let [b_h, b_m, b_s, b_e, b_j, b_v, b_vsh, b_i, b_rip, b_p, b_rl, b_rate, b_pct] =
+                            &mut bufs;
+                        // 時間（00時間00分00秒）— 2桁固定
+                        let hh = fmt::fmt_fixed((curr_sec / 3600).min(99) as u32, 2, 0, b_h);
+                        let mm = fmt::fmt_fixed(((curr_sec % 3600) / 60) as u32, 2, 0, b_m);
+                        let ss = fmt::fmt_fixed((curr_sec % 60) as u32, 2, 0, b_s);
+                        let e =
+                            fmt::fmt_fixed(fmt::round_scaled(total, 2), DISPLAY_INT_DIGITS, 2, b_e);
+                        // ジュールの列（Joule の E=… と同じ 7 桁.2 桁）。出さないときは空
+                        let (j_label, j, j_unit) = if SHOW_JOULES {
+                            let j_x100 = libm::round(acc[k].readout_energy_joules() * 100.0) as u32; // f32 だと 7 桁で丸まるので f64 のまま（as は飽和）
+                            ("  J=", fmt::fmt_fixed(j_x100, 7, 2, b_j), " J")
+                        } else {
+                            ("", "", "")
+                        };
+                        let v = fmt::fmt_fixed(v_mv.max(0) as u32, 2, 3, b_v);
+                        // シャント電圧: ±000.00 mV（±320 mV レンジ。振り切れていればシャント値/レンジを疑う）
+                        let vsh_sign = if shunt_uv < 0 { "-" } else { "+" };
+                        let vsh = fmt::fmt_fixed(shunt_uv.unsigned_abs() / 10, 3, 2, b_vsh); // 10µV 単位
+                                                                                             // 未校正で電流/電力が取れないときは同じ幅のダッシュ
+                        let i = match i_ua {
+                            Some(i_ua) => {
+                                fmt::fmt_fixed(i_ua.max(0) as u32 / 100, I_INT_DIGITS, 1, b_i)
+                            } // mA×10
+                            None => fmt::dashes(I_INT_DIGITS, 1, b_i),
+                        };
+                        // 表示区間内の電流のリップル（stddev / |mean|、%）。2 サンプル未満ならダッシュ
+                        let rip = if group[2].n >= 2 {
+                            fmt::fmt_fixed(
+                                fmt::round_scaled(group[2].ripple_percent(), 1),
+                                3,
+                                1,
+                                b_rip,
+                            ) // %×10
+                        } else {
+                            fmt::dashes(3, 1, b_rip)
+                        };
+                        let p = match p_uw {
+                            Some(p_uw) => {
+                                fmt::fmt_fixed(p_uw.max(0) as u32 / 100, P_INT_DIGITS, 1, b_p)
+                            } // mW×10
+                            None => fmt::dashes(P_INT_DIGITS, 1, b_p),
+                        };
+                        let rl = match i_ua {
+                            Some(i_ua) if i_ua.unsigned_abs() >= LOAD_MIN_UA as u32 => {
+                                let ohms = metrics::load_ohms(v_mv.max(0), i_ua);
+                                fmt::fmt_fixed(fmt::round_scaled(ohms, 2), 5, 2, b_rl)
+                                // Ω×100（上限は全桁 9）
+                            }
+                            _ => "-----.--",
+                        };
+                        let r = match mwh_min {
+                            Some(mwh_min) => {
+                                fmt::fmt_fixed(fmt::round_scaled(mwh_min, 2), 4, 2, b_rate)
+                            } // 消費ペース
+                            None => "----.--",
+                        };
+                        // BATTERY の容量に対する消費割合（1分集計と同じ percent_of_capacity）
+                        let pct = fmt::fmt_fixed(
+                            fmt::round_scaled(acc[k].consumed_percent(), 2),
+                            3,
+                            2,
+                            b_pct,
+                        );
+
+                        // 壁時計（START_EPOCH_S + 経過秒、24時間で折り返し）。未設定なら --:--:--
+                        let mut clock_buf = *b"--:--:--";
+                        if let Some(epoch) = START_EPOCH_S {
+                            let (h, m, s) = wall_clock_hms(epoch, (now - boot).to_secs());
+                            fmt::fmt_fixed(h as u32, 2, 0, &mut clock_buf[0..2]);
+                            fmt::fmt_fixed(m as u32, 2, 0, &mut clock_buf[3..5]);
+                            fmt::fmt_fixed(s as u32, 2, 0, &mut clock_buf[6..8]);
+                        }
+                        let clock = core::str::from_utf8(&clock_buf).unwrap_or("--:--:--");
+                        // 逆接続の疑い（I/P は 0 に丸めて表示しているので行末で示す）
+                        let polarity = if reversed[k].is_active() {
+                            "  !POL"
+                        } else {
+                            ""
+                        };
+
+                        if MEASURE_POWER {
+                            info!(
+                                "[{=str}] {=str}時間{=str}分{=str}秒 @{=str}  E={=str} {=str}{=str}{=str}{=str}  |  V={=str} V  Vsh={=str}{=str} mV  I={=str} mA  RIP={=str}%  P={=str} mW  RL={=str} ohm  R={=str} mWh/min  |  {=str}={=str}%{=str}",
+                                RAIL_LABELS[k], hh, mm, ss, clock, e, DISPLAY_UNIT.label(), j_label, j, j_unit, v, vsh_sign, vsh, i, rip, p, rl, r, BATTERY.label(), pct, polarity
+                            );
+                        } else {
+                            // 電圧だけの監視（MEASURE_POWER = false）: 経過時間とバス電圧のみ
+                            info!(
+                                "[{=str}] {=str}時間{=str}分{=str}秒 @{=str}  |  V={=str} V",
+                                RAIL_LABELS[k], hh, mm, ss, clock, v
+                            );
+                        }
+                        // 表示区間の電力（間引き時は平均）を MAX_POWER_MW 基準のバーで
+                        if let Some(p_uw) = p_uw.filter(|_| MAX_POWER_MW > 0) {
+                            let percent = termviz::pct(p_uw as f32 / 1000.0, MAX_POWER_MW as f32);
+                            let mut bar_buf = [0u8; termviz::BAR_W];
+                            let mut b_bar_pct = [0u8; 4];
+                            info!(
+                                "[{=str}] P |{=str}| {=str}%",
+                                RAIL_LABELS[k],
+                                termviz::render_bar(percent, &mut bar_buf),
+                                fmt::fmt_fixed(percent as u32, 3, 0, &mut b_bar_pct)
+                            );
+                        }
+                        // 同じ固定幅レイアウトを ASCII で USB シリアルへ（時刻は HH:MM:SS）。defmt を USB へ流すときは混ぜない
+                        #[cfg(all(feature = "usb-serial", not(feature = "log-usb")))]
+                        {
+                            let full = [
+                                "[",
+                                RAIL_LABELS[k],
+                                "] ",
+                                hh,
+                                ":",
+                                mm,
+                                ":",
+                                ss,
+                                " @",
+                                clock,
+                                "  E=",
+                                e,
+                                " ",
+                                DISPLAY_UNIT.label(),
+                                j_label,
+                                j,
+                                j_unit,
+                                "  |  V=",
+                                v,
+                                " V  Vsh=",
+                                vsh_sign,
+                                vsh,
+                                " mV  I=",
+                                i,
+                                " mA  RIP=",
+                                rip,
+                                "%  P=",
+                                p,
+                                " mW  RL=",
+                                rl,
+                                " ohm  R=",
+                                r,
+                                " mWh/min  |  ",
+                                BATTERY.label(),
+                                "=",
+                                pct,
+                                "%",
+                                polarity,
+                                "\r\n",
+                            ];
+                            let voltage_only = [
+                                "[",
+                                RAIL_LABELS[k],
+                                "] ",
+                                hh,
+                                ":",
+                                mm,
+                                ":",
+                                ss,
+                                " @",
+                                clock,
+                                "  |  V=",
+                                v,
+                                " V\r\n",
+                            ];
+                            let parts: &[&str] = if MEASURE_POWER { &full } else { &voltage_only };
+                            let mut buf = [0u8; usb_serial::LINE_MAX];
+                            let mut line = pico_va_monitor::ascii::Writer::new(&mut buf);
+                            for part in parts {
+                                line.push_str(part);
+                            }
+                            usb_serial::write(line.as_bytes());
+                        }
+                        last_printed_sec[k] = curr_sec;
+                        for st in group.iter_mut() {
+                            st.reset();
+                        }
+                    }
+                }
+                Ok(None) => {
+                    // 新規データ未到来。次サイクルへ。
+                }
+                Err(_e) => {
+                    warn!(
+                        "[{=str}] INA219 read error x{=u32}, re-init",
+                        RAIL_LABELS[k], INA_READ_ATTEMPTS
                     );
-                    last_printed_sec = curr_sec;
+                    if reconfigure_ina219(dev).is_err() {
+                        warn!("[{=str}] INA219 re-init failed", RAIL_LABELS[k]);
+                    }
+                    consecutive_errors[k] = consecutive_errors[k].saturating_add(1);
+                    fail_streak[k] = fail_streak[k].saturating_add(1);
                 }
             }
-            Ok(None) => {
-                // 新規データ未到来。次サイクルへ。
+            // 失敗が続いたら切断とみなして外す（積算は止まり、再検出を待つ）
+            if fail_streak[k] >= INA_DISCONNECT_ERRORS {
+                error!(
+                    "[{=str}] INA219 disconnected ({=u32} consecutive errors), re-detecting",
+                    RAIL_LABELS[k], fail_streak[k]
+                );
+                for m in recent[k].iter_recent() {
+                    info!("[{=str}] before disconnect: {}", RAIL_LABELS[k], m);
+                }
+                recent[k].clear();
+                *slot = None;
+                disconnected[k] = true;
+                fail_streak[k] = 0;
+                consecutive_errors[k] = 0;
+                last_p_uw[k] = 0;
+                last_v_mv[k] = 0;
+                last_i_ua[k] = 0;
             }
-            Err(_e) => {
-                warn!("INA219 read error");
+        }
+
+        // 固定レートの CSV: 全レールを毎周1行（最初のサンプルが来るまでのレールは出さない）
+        if CSV_FIXED_RATE {
+            for k in 0..INA_COUNT {
+                let Some((sample, fresh)) = csv_hold[k].next(csv_fresh[k]) else {
+                    continue;
+                };
+                let mut row = [0u8; csv::ROW_MAX];
+                let n = csv::format_fixed_row(
+                    RAIL_LABELS[k],
+                    elapsed_ms_total,
+                    fresh,
+                    &sample,
+                    acc[k].snapshot().1.min(i64::MAX as u128) as i64,
+                    &mut row,
+                );
+                println!("{=str}", core::str::from_utf8(&row[..n]).unwrap_or(""));
+            }
+        }
+
+        // 入力/出力レールがどちらも新しい値を持っていれば効率を1秒ごとに表示
+        if let Some((rin, rout)) = EFFICIENCY_RAILS {
+            let fresh = |k: usize| fresh_until_ms[k] > elapsed_ms_total;
+            if OUTPUT_HUMAN
+                && VERBOSITY >= Verbosity::Normal
+                && curr_sec > last_eff_sec
+                && curr_sec > 0
+                && fresh(rin)
+                && fresh(rout)
+            {
+                let eff = metrics::efficiency_percent(last_p_uw[rin], last_p_uw[rout]);
+                let mut b_eff = [0u8; 8];
+                let e = fmt::fmt_fixed(fmt::round_scaled(eff, 2), 3, 2, &mut b_eff);
+                info!(
+                    "[{=str}->{=str}] EFF={=str}%",
+                    RAIL_LABELS[rin], RAIL_LABELS[rout], e
+                );
+                last_eff_sec = curr_sec;
             }
         }
 
-        // 周期待ち（目安 500ms）
-        timer.delay_ms(LOOP_MS);
+        // 読み出しが戻ってきたのでフィード（I2C で固まったらここまで来ずにリセットされる）
+        watchdog.feed();
+
+        // 同じレールで失敗が続いたらバスが固まったとみなして復旧し、全レールを再設定
+        if consecutive_errors.iter().any(|&n| n >= I2C_RECOVERY_ERRORS) {
+            warn!(
+                "I2C: {=u32} consecutive errors, attempting bus recovery",
+                I2C_RECOVERY_ERRORS
+            );
+            recover_i2c_bus(
+                &bus,
+                &mut pac.RESETS,
+                clocks.system_clock.freq(),
+                &mut timer,
+            );
+            for (k, slot) in devs.iter_mut().enumerate() {
+                if let Some(dev) = slot.as_mut() {
+                    if reconfigure_ina219(dev).is_err() {
+                        warn!("[{=str}] INA219 re-init failed", RAIL_LABELS[k]);
+                    }
+                }
+            }
+            consecutive_errors = [0; INA_COUNT];
+        }
+
+        // 読み出し（とバス復旧）が済んだので次の周期まで INA219 の電源を切る
+        #[cfg(feature = "power-gate")]
+        let _ = ina_power.set_low();
+
+        // PERSIST_INTERVAL_MS ごとに主レールの累計値をフラッシュへ（空きスロットへの追記。セクタ消去を伴う回もあるのでフィード直後に行う）
+        // 積算器の i128/u128 はレコードの i64 に飽和させて詰める（µW·s で i64 を超えるのは約 29 万年分なので実用上は切れない）
+        if elapsed_ms_total.saturating_sub(last_persist_ms) >= PERSIST_INTERVAL_MS {
+            last_persist_ms = elapsed_ms_total;
+            persist_store.store(&persist::Totals {
+                charge_uas: acc[0]
+                    .net_charge_uas()
+                    .clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+                energy_uws: acc[0].snapshot().1.min(i64::MAX as u128) as i64,
+                uptime_ms: uptime_base_ms.saturating_add(elapsed_ms_total),
+            });
+        }
+
+        // リセットボタン：エッジ検出後 BUTTON_DEBOUNCE_MS 待ってまだ Low なら押下
+        let button_low = || {
+            cortex_m::interrupt::free(|cs| {
+                BUTTON
+                    .borrow(cs)
+                    .borrow_mut()
+                    .as_mut()
+                    .is_some_and(|b| b.is_low().unwrap_or(false))
+            })
+        };
+        if button_latched && !button_low() {
+            button_latched = false;
+        }
+        if cortex_m::interrupt::free(|cs| BUTTON_EDGE.borrow(cs).replace(false)) && !button_latched
+        {
+            timer.delay_ms(BUTTON_DEBOUNCE_MS);
+            if button_low() {
+                button_latched = true;
+                info!("Button: reset energy and minute aggregation");
+                // リセット前の区切りとして、レールごとの要約を1行ずつ残す
+                for (k, a) in acc.iter().enumerate() {
+                    if devs[k].is_some() {
+                        info!("[{=str}] session: {}", RAIL_LABELS[k], a.summary());
+                    }
+                }
+                info!(
+                    "[{=str}] session demand max ({=u32} s avg): {=f32} mW",
+                    RAIL_LABELS[0],
+                    DEMAND_WINDOW_S,
+                    demand.peak_demand_mw()
+                );
+                demand.reset();
+                near_fsr_warned = [false; INA_COUNT];
+                saturation_warned = [false; INA_COUNT];
+                start = timer.get_counter();
+                last = start;
+                for a in acc.iter_mut() {
+                    a.reset();
+                }
+                for st in minute_stats.iter_mut() {
+                    st.reset();
+                }
+                for st in test_stats.iter_mut() {
+                    st.reset();
+                }
+                for c in sample_counts.iter_mut() {
+                    c.reset();
+                }
+                end_of_test.reset();
+                fresh_until_ms = [0; INA_COUNT]; // 経過時間の起点が変わるので次のサンプルまで積算しない
+                valid_from_ms = [0; INA_COUNT];
+                uptime_base_ms = 0;
+                // 保存済みの値も消しておく（次の分境界を待たずに再起動しても戻らないように）
+                persist_store.store(&persist::Totals::default());
+                minute_agg.reset();
+                last_persist_ms = 0;
+                last_printed_sec = [0; INA_COUNT];
+                print_group = [[metrics::RunningStats::new(); 4]; INA_COUNT];
+                last_eff_sec = 0;
+            }
+        }
+
+        // 周期待ち（目安 500ms）。dt は毎周 get_counter() の差で測るので、眠った時間もそのまま積分される
+        // 周期変更コマンド（`p250` など）。変換時間より速くは読まないよう丸め、ウォッチドッグも追従させる
+        #[cfg(feature = "usb-serial")]
+        if let Some(req) = usb_serial::take_period_request() {
+            loop_ms = req.clamp(MIN_LOOP_MS, MAX_LOOP_MS);
+            adaptive.set_base(loop_ms);
+            watchdog.start(fugit::MicrosDurationU32::millis(watchdog_ms(loop_ms)));
+            info!("Loop period: {=u32} ms (requested {=u32})", loop_ms, req);
+            let mut buf = [0u8; usb_serial::LINE_MAX];
+            let mut line = pico_va_monitor::ascii::Writer::new(&mut buf);
+            line.push_str("period=");
+            line.push_u64(loop_ms as u64);
+            line.push_str(" ms\r\n");
+            usb_serial::write(line.as_bytes());
+        }
+        // 待機中は周期を延ばす（dt は実測なので周期が変わっても積算はそのまま正しい）
+        if IDLE_THRESHOLD_UA.is_some() {
+            if let Some(ms) = primary_active.and_then(|active| adaptive.update(active)) {
+                info!(
+                    "Loop period: {=u32} ms ({=str})",
+                    ms,
+                    if ms > loop_ms { "idle" } else { "active" }
+                );
+                loop_ms = ms;
+                watchdog.start(fugit::MicrosDurationU32::millis(watchdog_ms(loop_ms)));
+            }
+        }
+        let period = fugit::MicrosDurationU64::millis(loop_ms as u64);
+        next_wake += period;
+        let now = timer.get_counter();
+        if next_wake <= now {
+            next_wake = now + period;
+        }
+        sleep_until(&timer, next_wake);
     }
 }
 
-/// INA219 の初期化（校正 + 連続測定設定）
-/// 使用アドレスは `INA_ADDR`
-fn init_ina219<I2CIF>(i2c: I2CIF) -> Result<ina::SyncIna219<I2CIF, IntCalibration>, ()>
+/// 周期待ち用のアラーム（割り込みハンドラと共有）
+static ALARM: Mutex<RefCell<Option<Alarm0>>> = Mutex::new(RefCell::new(None));
+
+/// `wake` まで WFI で眠る。アラームを仕掛けてから、割り込み禁止の中で時刻確認と WFI を行い
+/// 「確認後・WFI 前に発火して起きられない」競合を避ける（保留中の割り込みは禁止中でも WFI を抜ける）
+fn sleep_until(timer: &Timer, wake: Instant) {
+    let armed = cortex_m::interrupt::free(|cs| {
+        ALARM
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .is_some_and(|a| a.schedule_at(wake).is_ok())
+    });
+    if !armed {
+        return;
+    }
+    loop {
+        let done = cortex_m::interrupt::free(|_| {
+            if timer.get_counter() >= wake {
+                return true;
+            }
+            cortex_m::asm::wfi();
+            false
+        });
+        if done {
+            break;
+        }
+    }
+}
+
+/// リセットボタンの入力ピン
+type ButtonPin = Pin<Gpio15, FunctionSioInput, PullUp>;
+
+/// リセットボタン（割り込みハンドラと共有）と、立ち下がりエッジを見たかどうか
+static BUTTON: Mutex<RefCell<Option<ButtonPin>>> = Mutex::new(RefCell::new(None));
+static BUTTON_EDGE: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// GPIO 割り込み：ボタンの立ち下がりを記録するだけ（判定はメインループでデバウンス後に行う）
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(button) = BUTTON.borrow(cs).borrow_mut().as_mut() {
+            button.clear_interrupt(GpioInterrupt::EdgeLow);
+            BUTTON_EDGE.borrow(cs).set(true);
+        }
+    });
+}
+
+/// ALARM0 の割り込み：フラグを落とすだけ（起床は WFI 側で判定）
+#[interrupt]
+fn TIMER_IRQ_0() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(alarm) = ALARM.borrow(cs).borrow_mut().as_mut() {
+            alarm.clear_interrupt();
+        }
+    });
+}
+
+/// INA219 のアドレス範囲（0x40..=0x4F）を走査し、ACK したアドレスを defmt に出す
+/// rp2040-hal は長さ 0 の write を受け付けないため、1 バイト read で応答を確認する
+/// （INA219 ではポインタレジスタの読み出しになるだけで副作用はない）
+fn scan_i2c_bus<I2CIF>(i2c: &mut I2CIF) -> usize
 where
     I2CIF: embedded_hal::i2c::I2c,
 {
-    info!("init: calc calibration...");
-
-    // 校正（IntCalibration）: current_LSB[µA/bit] は MAX_EXPECTED_AMPS / 2^15 で見積
-    let current_lsb_ua_per_bit = (MAX_EXPECTED_AMPS * 1_000_000.0 / 32768.0) as i64;
-    let r_shunt_uohm = (SHUNT_OHMS * 1_000_000.0) as u32;
-
-    info!("  current_lsb_ua_per_bit = {=i64}", current_lsb_ua_per_bit);
-    info!("  r_shunt_uohm          = {=u32}", r_shunt_uohm);
-
-    let calib = match IntCalibration::new(
-        ina::calibration::MicroAmpere(current_lsb_ua_per_bit),
-        r_shunt_uohm,
-    ) {
-        Some(c) => c,
-        None => {
-            error!("init: failed to create calibration");
-            return Err(());
+    info!("scan I2C bus 0x40..=0x4F...");
+    let mut found = 0;
+    let mut buf = [0u8; 1];
+    for addr in 0x40u8..=0x4F {
+        if i2c.read(addr, &mut buf).is_ok() {
+            info!("  found device at 0x{=u8:x}", addr);
+            found += 1;
         }
+    }
+    if found == 0 {
+        warn!("  no device responded");
+    }
+    found
+}
+
+/// I2C0（GPIO4=SDA / GPIO5=SCL）の具体型
+type I2cBus = I2C<
+    pac::I2C0,
+    (
+        Pin<Gpio4, FunctionI2C, PullUp>,
+        Pin<Gpio5, FunctionI2C, PullUp>,
+    ),
+>;
+
+/// I2C0 @ I2C_FREQ_KHZ を構成する。起動時とバス復旧後の再構成で共用
+fn i2c0_bus(
+    block: pac::I2C0,
+    sda: Pin<Gpio4, FunctionI2C, PullUp>,
+    scl: Pin<Gpio5, FunctionI2C, PullUp>,
+    resets: &mut pac::RESETS,
+    system_clock: fugit::HertzU32,
+) -> I2cBus {
+    I2C::i2c0(
+        block,
+        sda,
+        scl,
+        // 既定の 100kHz が無難。配線に問題がなければ I2C_FREQ_KHZ=400 まで引き上げ可能。
+        I2C_FREQ_KHZ.kHz(),
+        resets,
+        // system_clock 周波数を渡す（I2C タイミング計算に使用）。
+        // rp2040-hal の例と同様に system_clock を指定するのが正。
+        system_clock,
+    )
+}
+
+/// I2C バス復旧（SDA を握ったままのスレーブを解放する定番手順）
+/// I2C を解放して SCL を GPIO 出力にし、SDA が High に戻るまで最大 9 クロック送出、
+/// STOP 条件を作ってから I2C0 を構成し直す
+fn recover_i2c_bus(
+    bus: &RefCell<Option<I2cBus>>,
+    resets: &mut pac::RESETS,
+    system_clock: fugit::HertzU32,
+    delay: &mut impl DelayNs,
+) {
+    let Some(i2c) = bus.borrow_mut().take() else {
+        return;
     };
+    let (block, (sda, scl)) = i2c.free(resets);
+
+    let mut sda = sda.into_pull_up_input();
+    let mut scl = scl.into_push_pull_output();
+    let _ = scl.set_high();
+    delay.delay_us(5);
+    let mut pulses = 0u8;
+    while pulses < 9 && !sda.is_high().unwrap_or(false) {
+        let _ = scl.set_low();
+        delay.delay_us(5);
+        let _ = scl.set_high();
+        delay.delay_us(5);
+        pulses += 1;
+    }
+    let released = sda.is_high().unwrap_or(false);
 
-    // 設定：32Vレンジ / シャント±320mV（最大ゲイン）
-    let cfg = Configuration {
-        bus_voltage_range: BusVoltageRange::Fsr32v,
-        shunt_voltage_range: ShuntVoltageRange::Fsr320mv,
+    // STOP 条件（SCL High の間に SDA を Low → High）。SDA が解放されたときだけ駆動する
+    let sda = if released {
+        let _ = scl.set_low();
+        let mut sda = sda.into_push_pull_output();
+        let _ = sda.set_low();
+        delay.delay_us(5);
+        let _ = scl.set_high();
+        delay.delay_us(5);
+        let _ = sda.set_high();
+        delay.delay_us(5);
+        sda.into_pull_up_input()
+    } else {
+        sda
+    };
+    warn!(
+        "I2C bus recovery: {=u8} pulses, SDA released={=bool}",
+        pulses, released
+    );
+
+    let sda = sda.into_function::<FunctionI2C>();
+    let scl = scl.into_pull_up_input().into_function::<FunctionI2C>();
+    *bus.borrow_mut() = Some(i2c0_bus(block, sda, scl, resets, system_clock));
+}
+
+/// 共有バスの I2C エラー（復旧中でバスが取り外されている場合を含む）
+#[derive(Debug)]
+enum SharedI2cError<E> {
+    Bus(E),
+    Released,
+}
+
+impl<E: embedded_hal::i2c::Error> embedded_hal::i2c::Error for SharedI2cError<E> {
+    fn kind(&self) -> embedded_hal::i2c::ErrorKind {
+        match self {
+            SharedI2cError::Bus(e) => e.kind(),
+            SharedI2cError::Released => embedded_hal::i2c::ErrorKind::Other,
+        }
+    }
+}
+
+/// 同一バスを複数ドライバで共有するための I2C ラッパ（借用は各トランザクションの間だけ）
+struct SharedI2c<'a, I2CIF>(&'a RefCell<Option<I2CIF>>);
+
+impl<I2CIF: embedded_hal::i2c::ErrorType> embedded_hal::i2c::ErrorType for SharedI2c<'_, I2CIF> {
+    type Error = SharedI2cError<I2CIF::Error>;
+}
+
+impl<I2CIF: embedded_hal::i2c::I2c> embedded_hal::i2c::I2c for SharedI2c<'_, I2CIF> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        match self.0.borrow_mut().as_mut() {
+            Some(i2c) => i2c
+                .transaction(address, operations)
+                .map_err(SharedI2cError::Bus),
+            None => Err(SharedI2cError::Released),
+        }
+    }
+}
+
+/// `INA_ADDRS` の全アドレスを初期化。成功したものだけ `Some`（失敗はログして続行）
+fn init_ina219_all<I2CIF>(
+    bus: &RefCell<Option<I2CIF>>,
+) -> [Option<InaDevice<SharedI2c<'_, I2CIF>>>; INA_COUNT]
+where
+    I2CIF: embedded_hal::i2c::I2c,
+{
+    core::array::from_fn(|k| match init_ina219(SharedI2c(bus), INA_ADDRS[k]) {
+        Ok(dev) => {
+            info!("[{=str}] 0x{=u8:x}: OK", RAIL_LABELS[k], INA_ADDRS[k]);
+            Some(dev)
+        }
+        Err(_) => {
+            warn!(
+                "[{=str}] 0x{=u8:x}: NG (skip)",
+                RAIL_LABELS[k], INA_ADDRS[k]
+            );
+            None
+        }
+    })
+}
+
+/// 校正値（IntCalibration）: `CURRENT_LSB_UA` と `SHUNT_UOHM` から作る
+fn ina_calibration() -> Option<IntCalibration> {
+    IntCalibration::new(ina::calibration::MicroAmpere(CURRENT_LSB_UA), SHUNT_UOHM)
+}
+
+/// 試験終了の要約（主レールの累計と、V[V]/I[mA] のサンプル統計 `stats`）を複数行で出す
+fn log_test_summary(acc: &metrics::Accumulators, stats: &[metrics::RunningStats; 2], idle_ms: u64) {
+    let summary = acc.summary();
+    info!(
+        "=== [{=str}] end of test (idle {=u64} s) ===",
+        RAIL_LABELS[0],
+        idle_ms / 1000
+    );
+    info!(
+        "  energy: {=f32} mWh  charge: {=f32} mAh  time: {=u64} s",
+        summary.energy_mwh,
+        summary.charge_mah,
+        summary.uptime_ms / 1000
+    );
+    for (label, st) in ["V[V]", "I[mA]"].iter().zip(stats.iter()) {
+        if st.n == 0 {
+            continue;
+        }
+        info!(
+            "  {=str}: mean={=f32} min={=f32} max={=f32}",
+            label, st.mean, st.min, st.max
+        );
+    }
+    info!(
+        "  peak P: {=f32} mW  avg P: {=f32} mW",
+        acc.peak_power_mw(),
+        summary.avg_power_mw
+    );
+    // カットオフなしでは全区間がアクティブになり、デューティ比は意味を持たない
+    if acc.current_cutoff_ma > 0 {
+        info!(
+            "  duty: {=f32}% (|I| >= {=u32} mA)",
+            summary.duty_cycle * 100.0,
+            acc.current_cutoff_ma
+        );
+    } else {
+        info!("  duty: n/a (CURRENT_CUTOFF_MA = 0)");
+    }
+    info!(
+        "  battery equiv: {=str}={=f32}  AA(alkaline)={=f32}  AAA(alkaline)={=f32}",
+        BATTERY.label(),
+        metrics::battery_equiv_for(summary.energy_wh, BATTERY),
+        metrics::battery_equiv_for(summary.energy_wh, metrics::BatteryType::AlkalineAa),
+        metrics::battery_equiv_for(summary.energy_wh, metrics::BatteryType::AlkalineAaa)
+    );
+}
+
+/// 設定：レンジは `BUS_RANGE`・`SHUNT_RANGE` / ADC は `SHUNT_ADC`・`BUS_ADC`
+fn ina_configuration() -> Configuration {
+    Configuration {
+        bus_voltage_range: BUS_RANGE,
+        shunt_voltage_range: SHUNT_RANGE,
+        bus_resolution: BUS_ADC,
+        shunt_resolution: SHUNT_ADC,
         ..Default::default()
+    }
+}
+
+/// INA219 の初期化（校正 + 連続測定設定）
+fn init_ina219<I2CIF>(i2c: I2CIF, addr: u8) -> Result<InaDevice<I2CIF>, ()>
+where
+    I2CIF: embedded_hal::i2c::I2c,
+{
+    // 電圧だけの監視なら校正値を作らず、未校正のまま開く
+    let calib = if MEASURE_POWER {
+        info!("init: calc calibration...");
+        let calib = ina_calibration();
+        if calib.is_none() {
+            warn!("init: failed to create calibration, falling back to uncalibrated (bus/shunt voltage only)");
+        }
+        calib
+    } else {
+        info!("init: MEASURE_POWER off, skipping calibration (bus voltage only)");
+        None
     };
 
-    // 使用アドレスは `INA_ADDR`
-    let address = match Address::from_byte(INA_ADDR) {
+    let address = match Address::from_byte(addr) {
         Ok(a) => a,
         Err(_) => {
-            error!("Invalid INA219 address: 0x{=u8:x}", INA_ADDR);
+            error!("Invalid INA219 address: 0x{=u8:x}", addr);
             return Err(());
         }
     };
 
-    info!("init at address 0x{=u8:x}...", INA_ADDR);
-    let mut dev = match ina::SyncIna219::new_calibrated(i2c, address, calib) {
+    info!("init at address 0x{=u8:x}...", addr);
+    let opened = match calib {
+        Some(calib) => {
+            ina::SyncIna219::new_calibrated(i2c, address, calib).map(InaDevice::Calibrated)
+        }
+        None => ina::SyncIna219::new(i2c, address).map(InaDevice::Uncalibrated),
+    };
+    let mut dev = match opened {
         Ok(d) => d,
         Err(e) => {
             match e.reason {
@@ -451,29 +1865,171 @@ where
         }
     };
 
-    dev.set_configuration(cfg).map_err(|_| ())?;
-    info!("INA219 initialized at 0x{=u8:x}", INA_ADDR);
+    let cfg = ina_configuration();
+    write_configuration(&mut dev, cfg)?;
+    info!("INA219 initialized at 0x{=u8:x}", addr);
+    if let Some(us) = cfg.conversion_time_us() {
+        info!("  conversion time = {=u32} us", us);
+    }
     Ok(dev)
 }
 
-/// 1サイクル分の計測値取得（mV, µA, µW）
-fn ina_next<I2CIF>(
-    dev: &mut ina::SyncIna219<I2CIF, IntCalibration>,
-) -> Result<Option<(i32, i32, i32)>, ()>
+/// 校正済み、または校正値を作れなかったときの未校正で開いた INA219
+enum InaDevice<I2CIF> {
+    Calibrated(ina::SyncIna219<I2CIF, IntCalibration>),
+    /// バス電圧/シャント電圧のみ（電流/電力レジスタは校正なしでは意味を持たない）
+    Uncalibrated(ina::SyncIna219<I2CIF, UnCalibrated>),
+}
+
+/// 既存デバイスに校正値と設定を書き直す（読み出し失敗が続いたときの再初期化）
+fn reconfigure_ina219<I2CIF>(dev: &mut InaDevice<I2CIF>) -> Result<(), ()>
 where
     I2CIF: embedded_hal::i2c::I2c,
 {
+    match dev {
+        InaDevice::Calibrated(d) => {
+            let calib = ina_calibration().ok_or(())?;
+            d.calibrate(calib).map_err(|_| ())?;
+        }
+        InaDevice::Uncalibrated(_) => {}
+    }
+    write_configuration(dev, ina_configuration())
+}
+
+/// 設定レジスタを書いて読み戻し、食い違えば1回だけ書き直す（バスが不安定だと書けたつもりで別設定のまま動くため）
+fn write_configuration<I2CIF>(dev: &mut InaDevice<I2CIF>, cfg: Configuration) -> Result<(), ()>
+where
+    I2CIF: embedded_hal::i2c::I2c,
+{
+    for attempt in 0..2 {
+        let read = match dev {
+            InaDevice::Calibrated(d) => {
+                d.set_configuration(cfg).map_err(|_| ())?;
+                d.configuration().map_err(|_| ())?
+            }
+            InaDevice::Uncalibrated(d) => {
+                d.set_configuration(cfg).map_err(|_| ())?;
+                d.configuration().map_err(|_| ())?
+            }
+        };
+        match config_mismatch(&cfg, &read) {
+            None => return Ok(()),
+            Some(field) if attempt == 0 => {
+                warn!("INA219 config readback mismatch ({=str}), rewriting", field)
+            }
+            Some(field) => error!(
+                "INA219 config readback mismatch ({=str}) after rewrite",
+                field
+            ),
+        }
+    }
+    Err(())
+}
+
+/// 符号の取り決めをそろえる（INVERT_CURRENT なら反転）。`ina_next` の直後に通す
+fn normalize_sign(m: Measurement) -> Measurement {
+    sample::normalize_sign(m, INVERT_CURRENT)
+}
+
+/// 1サイクル分の計測値取得
+/// INA219 が OVF ビットを立てた場合は電流/電力レジスタが不正なので、
+/// シャント電圧から求め直した値にして `overflow` を立てる（`Measurement::from_overflow_regs`）
+fn ina_next<I2CIF>(dev: &mut InaDevice<I2CIF>) -> Result<Option<Measurement>, ()>
+where
+    I2CIF: embedded_hal::i2c::I2c,
+{
+    let dev = match dev {
+        InaDevice::Calibrated(d) => d,
+        InaDevice::Uncalibrated(d) => {
+            // 未校正：電圧の2レジスタのみ意味がある
+            return match d.next_measurement() {
+                Ok(Some(m)) => Ok(Some(Measurement::from_regs(
+                    m.shunt_voltage.shunt_voltage_10uv(),
+                    m.bus_voltage.voltage_4mv(),
+                ))),
+                Ok(None) => Ok(None),
+                Err(_) => Err(()),
+            };
+        }
+    };
     // next_measurement(): Ok(Some(..)) のときのみ新データ
-    match dev.next_measurement().map_err(|_| ())? {
-        Some(m) => {
-            // 期待する単位：Bus電圧[mV] / 電流[µA] / 電力[µW]
-            let v_mv = m.bus_voltage.voltage_mv() as i32;
-            let i_ua = m.current.0 as i32;
-            let p_uw = m.power.0 as i32;
-            Ok(Some((v_mv, i_ua, p_uw)))
-        }
-        None => Ok(None),
+    match dev.next_measurement() {
+        Ok(Some(m)) => Ok(Some(Measurement {
+            current_ua: Some(m.current.0 as i32),
+            power_uw: Some(m.power.0 as i32),
+            ..Measurement::from_regs(
+                m.shunt_voltage.shunt_voltage_10uv(),
+                m.bus_voltage.voltage_4mv(),
+            )
+        })),
+        Ok(None) => Ok(None),
+        Err(MeasurementError::MathOverflow(m)) => Ok(Some(Measurement::from_overflow_regs(
+            m.shunt_voltage.shunt_voltage_10uv(),
+            m.bus_voltage.voltage_4mv(),
+            SHUNT_UOHM,
+        ))),
+        Err(_) => Err(()),
+    }
+}
+
+/// 新しい変換ごとに電流 [mA] を `samples` 個集めて統計を返す（各読み出しの前に `wait` で変換を待つ）
+/// 読み出し失敗や未校正（電流なし）のサンプルは数えず、試行は `samples` の 4 倍で打ち切る
+fn calibrate_noise_floor<I2CIF>(
+    dev: &mut InaDevice<I2CIF>,
+    samples: u32,
+    mut wait: impl FnMut(),
+) -> metrics::RunningStats
+where
+    I2CIF: embedded_hal::i2c::I2c,
+{
+    let mut st = metrics::RunningStats::new();
+    for _ in 0..samples.saturating_mul(4) {
+        if st.n >= samples as u64 {
+            break;
+        }
+        wait();
+        if let Ok(Some(Measurement {
+            current_ua: Some(i_ua),
+            ..
+        })) = ina_next(dev).map(|m| m.map(normalize_sign))
+        {
+            st.update(i_ua as f32 / 1000.0);
+        }
+    }
+    st
+}
+
+/// 主レールが最初の「有効で 0 でない」サンプルを返すまで待つ（`attempts` 回 `wait()` して読む、見つからなければ `None`）
+/// 0 でないかの判定は電流（未校正ならシャント電圧）
+fn wait_first_sample<I2CIF>(
+    dev: &mut InaDevice<I2CIF>,
+    attempts: u32,
+    mut wait: impl FnMut(),
+) -> Option<Measurement>
+where
+    I2CIF: embedded_hal::i2c::I2c,
+{
+    for _ in 0..attempts.max(1) {
+        wait();
+        if let Ok(Some(m)) = ina_next(dev).map(|m| m.map(normalize_sign)) {
+            if m.current_ua.map_or(m.shunt_uv != 0, |i_ua| i_ua != 0) {
+                return Some(m);
+            }
+        }
     }
+    None
+}
+
+/// バス電圧/シャント電圧がフルスケールの FULL_SCALE_WARN_PCT % を超えていれば、どちらか（"bus" / "shunt"）を返す
+/// シャントは絶対値で判定（逆向きの電流でも振り切れるため）
+fn near_full_scale(bus_mv: i32, shunt_uv: i32) -> Option<&'static str> {
+    sample::near_full_scale(
+        bus_mv,
+        shunt_uv,
+        BUS_FSR_MV,
+        SHUNT_FSR_UV,
+        FULL_SCALE_WARN_PCT,
+    )
 }
 
 // BOOT2（必須）
@@ -1,108 +1,2654 @@
 //! 統計・積算ロジック（no_std）
-//! - 逐次統計（Welford法）: RunningStats
-//! - 積算（固定小数）: Accumulators（電荷[µA·s]、エネルギー[µW·s]、稼働時間[ms]）
+//! - 逐次統計（Welford法）: RunningStats（電流のリップル: ripple_percent）
+//! - 指数移動平均: Ewma（消費ペース mWh/分 の平滑化: EnergyRate / mwh_per_min）
+//! - 直近 N サンプルの窓統計: RollingStats（ブラウンアウト判定用の窓内最小: window_min、窓内中央値による外れ値除去: OutlierFilter、窓平均の最大値: MaxDemand）
+//! - P² 法の分位点推定: Quantile
+//! - 等幅ヒストグラム: Histogram
+//! - ヒステリシス付き閾値判定: Hysteresis / 連続 N 回で発報: Sustained / 待機の継続で試験終了を検出: IdleTrigger
+//! - 読み出し結果の内訳とサンプル収率: SampleCounters（yield_percent）
+//! - 積算（固定小数）: Accumulators（電荷[µA·s]、エネルギー[µW·s]、稼働時間[ms]）、表示単位の換算: DisplayUnit
+//!   （初期電荷からのクーロンカウンタ式の残量: remaining_mah / soc_coulomb_percent）
+//! - 読み出し途切れ時の積算区間の切り詰め: held_ms / held_ms_from（最初のサンプルより前は使わない） / 異常な dt の頭打ち: clamp_dt_ms
+//! - 集計窓（既定 1 分）ごとの時間重み付き集計: MinuteAggregator（窓の境界をまたぐ区間は分割）
+//! - 待機中の計測周期の引き延ばし: AdaptivePeriod
+//! - 2 レール間の変換効率: efficiency_percent / 見かけの負荷抵抗: load_ohms
+//! - 起点からの経過秒を時刻に: wall_clock_hms
+//! - 電池本数換算: battery_equiv / battery_equiv_for（BatteryType の代表容量）、低温での目減り: derate_capacity
 
 #![allow(dead_code)]
 
-
+use crate::history::SampleHistory;
 
 /// 逐次統計（Welford法）
-/// 平均・分散・標準偏差・最小・最大を保持
+/// 平均・分散・標準偏差・最小・最大・RMS を保持（`update_at` なら最小/最大を記録した時刻も）
 #[derive(Clone, Copy, Default)]
 pub struct RunningStats {
     pub n: u64,
     pub mean: f32,
     m2: f32,
+    /// 二乗和（長時間でも桁落ちしないよう f64）
+    sum_sq: f64,
     pub min: f32,
     pub max: f32,
+    /// 現在の最小/最大を記録した時刻 [ms]（`update` のみで入力した場合は 0）
+    min_at_ms: u64,
+    max_at_ms: u64,
 }
 
 impl RunningStats {
     /// 新規作成
     pub const fn new() -> Self {
-        Self { n: 0, mean: 0.0, m2: 0.0, min: f32::INFINITY, max: f32::NEG_INFINITY }
+        Self {
+            n: 0,
+            mean: 0.0,
+            m2: 0.0,
+            sum_sq: 0.0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            min_at_ms: 0,
+            max_at_ms: 0,
+        }
+    }
+
+    /// 初期状態（`new()` と同じ）に戻す
+    pub fn reset(&mut self) {
+        *self = Self::new();
     }
 
-    /// 値を追加入力
+    /// 値を追加入力（NaN/∞ は平均・M2 を汚染するため無視）
     pub fn update(&mut self, x: f32) {
+        if !x.is_finite() {
+            return;
+        }
         self.n += 1;
         let n_f = self.n as f32;
         let delta = x - self.mean;
         self.mean += delta / n_f;
         let delta2 = x - self.mean;
         self.m2 += delta * delta2;
-        if x < self.min { self.min = x; }
-        if x > self.max { self.max = x; }
+        self.sum_sq += (x as f64) * (x as f64);
+        if x < self.min {
+            self.min = x;
+        }
+        if x > self.max {
+            self.max = x;
+        }
+    }
+
+    /// 時刻 `t_ms` 付きで値を追加入力。最小/最大を更新したときだけその時刻を記録する（同値では更新しない）
+    pub fn update_at(&mut self, x: f32, t_ms: u64) {
+        if !x.is_finite() {
+            return;
+        }
+        if x < self.min {
+            self.min_at_ms = t_ms;
+        }
+        if x > self.max {
+            self.max_at_ms = t_ms;
+        }
+        self.update(x);
+    }
+
+    /// 現在の最小を記録した時刻 [ms]
+    pub fn min_at_ms(&self) -> u64 {
+        self.min_at_ms
+    }
+
+    /// 現在の最大を記録した時刻 [ms]
+    pub fn max_at_ms(&self) -> u64 {
+        self.max_at_ms
+    }
+
+    /// スライスからまとめて入力。NaN/∞ は読み飛ばし、採用したサンプル数を返す
+    pub fn update_slice(&mut self, xs: &[f32]) -> usize {
+        let mut accepted = 0;
+        for &x in xs {
+            if !x.is_finite() {
+                continue;
+            }
+            self.update(x);
+            accepted += 1;
+        }
+        accepted
+    }
+
+    /// 別の統計を合算（Chan らの並列分散公式）
+    /// M2 = M2_a + M2_b + δ² · n_a · n_b / n
+    pub fn merge(&mut self, other: &RunningStats) {
+        if other.n == 0 {
+            return;
+        }
+        if self.n == 0 {
+            *self = *other;
+            return;
+        }
+        let n_a = self.n as f32;
+        let n_b = other.n as f32;
+        let n = self.n + other.n;
+        let n_f = n as f32;
+        let delta = other.mean - self.mean;
+        self.mean += delta * n_b / n_f;
+        self.m2 += other.m2 + delta * delta * n_a * n_b / n_f;
+        self.sum_sq += other.sum_sq;
+        self.n = n;
+        if other.min < self.min {
+            self.min = other.min;
+            self.min_at_ms = other.min_at_ms;
+        }
+        if other.max > self.max {
+            self.max = other.max;
+            self.max_at_ms = other.max_at_ms;
+        }
     }
 
     /// 標本分散
     pub fn variance(&self) -> f32 {
-        if self.n < 2 { 0.0 } else { self.m2 / (self.n as f32 - 1.0) }
+        if self.n < 2 {
+            0.0
+        } else {
+            self.m2 / (self.n as f32 - 1.0)
+        }
     }
 
     /// 標準偏差
-    pub fn stddev(&self) -> f32 { libm::sqrtf(self.variance()) }
+    pub fn stddev(&self) -> f32 {
+        libm::sqrtf(self.variance())
+    }
+
+    /// 変動係数（stddev / mean）。mean == 0 のときは 0.0
+    pub fn cv(&self) -> f32 {
+        if self.mean == 0.0 {
+            0.0
+        } else {
+            self.stddev() / self.mean
+        }
+    }
+
+    /// リップル（%）: 100 * stddev / |mean|。スイッチング負荷の電流の揺れの目安
+    /// 2 サンプル未満や mean == 0 のときは 0.0
+    pub fn ripple_percent(&self) -> f32 {
+        if self.n < 2 || self.mean == 0.0 {
+            return 0.0;
+        }
+        100.0 * self.stddev() / self.mean.abs()
+    }
+
+    /// 平均の標準誤差（stddev / √n）
+    pub fn stderr(&self) -> f32 {
+        if self.n == 0 {
+            0.0
+        } else {
+            self.stddev() / libm::sqrtf(self.n as f32)
+        }
+    }
+
+    /// 二乗平均平方根（RMS）
+    pub fn rms(&self) -> f32 {
+        if self.n == 0 {
+            0.0
+        } else {
+            libm::sqrt(self.sum_sq / self.n as f64) as f32
+        }
+    }
 }
 
-/// 積算器（固定小数）：
-/// - 累計電荷: µA·s（u128）
-/// - 累計エネルギー: µW·s（u128）
-/// - 稼働時間: ms（u64）
-pub struct Accumulators {
-    charge_uas: u128,
-    energy_uws: u128,
-    pub uptime_ms: u64,
-    /// 微小電流のカットオフ（mA）。|I| < cutoff の場合0扱い
-    pub current_cutoff_ma: u32,
+/// `info!("{}", stats)` で1行出力（M2 の代わりに標準偏差を表示）
+impl defmt::Format for RunningStats {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "n={=u64} mean={=f32} sd={=f32} min={=f32} max={=f32}",
+            self.n,
+            self.mean,
+            self.stddev(),
+            self.min,
+            self.max
+        )
+    }
 }
 
-impl Accumulators {
-    pub const fn new(cutoff_ma: u32) -> Self {
-        Self { charge_uas: 0, energy_uws: 0, uptime_ms: 0, current_cutoff_ma: cutoff_ma }
+/// 指数移動平均（EMA）
+/// ema = alpha*x + (1-alpha)*ema。最初のサンプルはそのまま初期値とする
+#[derive(Clone, Copy)]
+pub struct Ewma {
+    alpha: f32,
+    value: f32,
+    seeded: bool,
+}
+
+impl Ewma {
+    /// 新規作成。alpha は (0, 1] にクランプ（NaN/0以下は最小正値扱い）
+    pub fn new(alpha: f32) -> Self {
+        let alpha = if alpha > 1.0 {
+            1.0
+        } else if alpha > 0.0 {
+            alpha
+        } else {
+            f32::EPSILON
+        };
+        Self {
+            alpha,
+            value: 0.0,
+            seeded: false,
+        }
     }
 
-    /// 積算更新
-    /// v_v: V, i_ma: mA, p_mw: mW, dt_ms: 経過時間[ms]
-    pub fn update(&mut self, _v_v: f32, i_ma: f32, p_mw: f32, dt_ms: u32) {
-        self.uptime_ms = self.uptime_ms.saturating_add(dt_ms as u64);
+    /// 値を追加入力（NaN/∞ は平均を汚染し続けるため無視）
+    pub fn update(&mut self, x: f32) {
+        if !x.is_finite() {
+            return;
+        }
+        if self.seeded {
+            self.value = self.alpha * x + (1.0 - self.alpha) * self.value;
+        } else {
+            self.value = x;
+            self.seeded = true;
+        }
+    }
 
-        // 微小電流カットオフ
-        let i_ma_eff = if i_ma.abs() < self.current_cutoff_ma as f32 { 0.0 } else { i_ma };
+    /// 現在の平均値（未入力なら 0.0）
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
 
-        // 電荷: µA·s = (i[mA]*1000)[µA] * (dt[ms]/1000)[s]
-        //      = i[mA] * dt[ms]
-        // 単位合わせ：i[mA]*dt[ms] = (mA·ms) = µA·s
-        let dq_uas = (i_ma_eff as f64) * (dt_ms as f64);
-        if dq_uas.is_finite() && dq_uas >= 0.0 {
-            self.charge_uas = self.charge_uas.saturating_add(dq_uas as u128);
+/// 今の電力が続いた場合の消費ペース [mWh/分]: P[mW] × (1/60)[h]
+/// 例: 1000 mW → 16.67 mWh/分
+pub fn mwh_per_min(power_mw: f32) -> f32 {
+    power_mw / 60.0
+}
+
+/// 消費ペース [mWh/分] の表示用（Ewma で平滑化、サンプルごとのばらつきを抑える）
+#[derive(Clone, Copy)]
+pub struct EnergyRate {
+    ewma: Ewma,
+}
+
+impl EnergyRate {
+    /// 新規作成。alpha は Ewma と同じ（大きいほど追従が速い）
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            ewma: Ewma::new(alpha),
         }
+    }
 
-        // エネルギー: µW·s = (p[mW]*1000)[µW] * (dt[ms]/1000)[s]
-        //           = p[mW] * dt[ms]
-        let de_uws = (p_mw as f64) * (dt_ms as f64);
-        if de_uws.is_finite() && de_uws >= 0.0 {
-            self.energy_uws = self.energy_uws.saturating_add(de_uws as u128);
+    /// 電力 [mW] を入力し、平滑化後の mWh/分 を返す
+    pub fn update(&mut self, power_mw: f32) -> f32 {
+        self.ewma.update(mwh_per_min(power_mw));
+        self.ewma.value()
+    }
+
+    /// 平滑化後の mWh/分（未入力なら 0.0）
+    pub fn value(&self) -> f32 {
+        self.ewma.value()
+    }
+}
+
+/// 直近 N サンプルの窓統計（`SampleHistory` のリングバッファ、アロケーションなし）
+/// 満杯時は最古のサンプルを上書きする
+#[derive(Clone, Copy)]
+pub struct RollingStats<const N: usize> {
+    window: SampleHistory<f32, N>,
+}
+
+impl<const N: usize> RollingStats<N> {
+    /// 新規作成
+    pub const fn new() -> Self {
+        Self {
+            window: SampleHistory::filled(0.0),
         }
     }
 
-    /// 累計電荷の読み出し（mAh）
-    pub fn readout_charge_mah(&self) -> f32 {
-        // 1 mAh = 3_600_000 µA·s
-        (self.charge_uas as f64 / 3_600_000.0) as f32
+    /// 値を追加入力（NaN/∞ は無視）
+    pub fn push(&mut self, x: f32) {
+        if !x.is_finite() {
+            return;
+        }
+        self.window.push(x);
     }
 
-    /// 累計エネルギーの読み出し（mWh, Wh）
-    pub fn readout_energy(&self) -> (f32, f32) {
-        // 1 mWh = 3_600_000 µW·s, 1 Wh = 1000 mWh
-        let mwh = (self.energy_uws as f64 / 3_600_000.0) as f32;
-        let wh = mwh / 1000.0;
-        (mwh, wh)
+    /// 窓内のサンプル数
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    /// 窓が空か
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    /// 窓内の有効サンプル（順不同）
+    fn samples(&self) -> &[f32] {
+        self.window.as_unordered()
+    }
+
+    /// 窓内平均（空なら 0.0）
+    pub fn mean(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self.samples().iter().sum();
+        sum / self.len() as f32
+    }
+
+    /// 窓内最小（空なら +∞）
+    pub fn min(&self) -> f32 {
+        self.samples()
+            .iter()
+            .fold(f32::INFINITY, |m, &x| if x < m { x } else { m })
+    }
+
+    /// 窓内最大（空なら -∞）
+    pub fn max(&self) -> f32 {
+        self.samples()
+            .iter()
+            .fold(f32::NEG_INFINITY, |m, &x| if x > m { x } else { m })
+    }
+
+    /// 直近 N サンプルの最小（空なら `None`）。窓から外れた古い谷は含まない
+    /// 起動後の最小（`RunningStats::min`）と違い最初の落ち込みに張り付かないので、電圧降下（ブラウンアウト）の判定向け
+    /// N は小さい前提で毎回走査する
+    pub fn window_min(&self) -> Option<f32> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.min())
+        }
+    }
+
+    /// 窓内中央値（偶数個なら中央2つの平均、空なら 0.0）
+    pub fn median(&self) -> f32 {
+        let len = self.len();
+        if len == 0 {
+            return 0.0;
+        }
+        let mut s = [0.0; N];
+        s[..len].copy_from_slice(self.samples());
+        sort_prefix(&mut s, len);
+        let mid = len / 2;
+        if len % 2 == 1 {
+            s[mid]
+        } else {
+            (s[mid - 1] + s[mid]) / 2.0
+        }
     }
 }
 
-/// 電池本数換算（AA/AAA）。E_Wh / 代表容量[Wh]
-pub fn battery_equiv(wh: f32, e_aa_wh: f32, e_aaa_wh: f32) -> (f32, f32) {
-    let aa = if e_aa_wh > 0.0 { wh / e_aa_wh } else { 0.0 };
-    let aaa = if e_aaa_wh > 0.0 { wh / e_aaa_wh } else { 0.0 };
-    (aa, aaa)
+/// 最大需要電力（電力会社のデマンド計と同じ考え方）
+/// 直近 N サンプルの平均電力の最大値を保持する。単発のピークではなく、窓の長さ続いた負荷を表す
+/// 窓が埋まるまでは記録しない（窓より短い立ち上がりでピークが決まらないように）
+#[derive(Clone, Copy)]
+pub struct MaxDemand<const N: usize> {
+    window: RollingStats<N>,
+    peak_mw: f32,
+}
+
+impl<const N: usize> MaxDemand<N> {
+    /// 新規作成
+    pub const fn new() -> Self {
+        Self {
+            window: RollingStats::new(),
+            peak_mw: 0.0,
+        }
+    }
+
+    /// 電力 [mW] を入力（NaN/∞ は無視）
+    pub fn update(&mut self, power_mw: f32) {
+        if !power_mw.is_finite() {
+            return;
+        }
+        self.window.push(power_mw);
+        if self.window.len() == N {
+            let avg = self.window.mean();
+            if avg > self.peak_mw {
+                self.peak_mw = avg;
+            }
+        }
+    }
+
+    /// 最大需要電力 [mW]（窓が一度も埋まっていなければ 0.0）
+    pub fn peak_demand_mw(&self) -> f32 {
+        self.peak_mw
+    }
+
+    /// 窓とピークを捨てる
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<const N: usize> Default for MaxDemand<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 外れ値除去（直近 N サンプルの中央値の factor 倍を超える値を弾く）
+/// - 判定は絶対値で行い、|x| が floor 以下なら常に通す（待機中の微小電流で中央値≈0 のときの誤判定を防ぐ）
+/// - 弾いた値も窓には入れる（負荷が本当に跳ね上がった場合は窓の半分ほどで中央値が追いつく）
+/// - 窓が半分埋まるまでは判定しない
+#[derive(Clone, Copy)]
+pub struct OutlierFilter<const N: usize> {
+    window: RollingStats<N>,
+    factor: f32,
+    floor: f32,
+}
+
+impl<const N: usize> OutlierFilter<N> {
+    /// 新規作成。factor <= 0（または NaN）なら無効（常に通す）
+    pub const fn new(factor: f32, floor: f32) -> Self {
+        Self {
+            window: RollingStats::new(),
+            factor,
+            floor,
+        }
+    }
+
+    /// 値を入力し、外れ値なら true（呼び出し側で積算/統計から外す）
+    pub fn reject(&mut self, x: f32) -> bool {
+        let ax = if x < 0.0 { -x } else { x };
+        let outlier = self.factor > 0.0
+            && self.window.len() * 2 >= N
+            && ax > self.floor
+            && ax > self.factor * self.window.median();
+        self.window.push(ax);
+        outlier
+    }
+
+    /// 窓を空にする（判定はまた窓が半分埋まるまで止まる）
+    pub fn reset(&mut self) {
+        self.window = RollingStats::new();
+    }
+}
+
+impl<const N: usize> Default for RollingStats<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// P² 法によるオンライン分位点推定（Jain & Chlamtac）
+/// 5 個のマーカー（高さ q / 位置 n / 理想位置 np）のみ保持し、全サンプルは保存しない
+#[derive(Clone, Copy)]
+pub struct Quantile {
+    p: f32,
+    q: [f32; 5],
+    n: [f32; 5],
+    np: [f32; 5],
+    dn: [f32; 5],
+    count: u32,
+}
+
+impl Quantile {
+    /// 新規作成。p は目標確率（0.5 で中央値、0.95 で 95 パーセンタイル）。[0, 1] にクランプ
+    pub fn new(p: f32) -> Self {
+        let p = if p > 1.0 {
+            1.0
+        } else if p > 0.0 {
+            p
+        } else {
+            0.0
+        };
+        Self {
+            p,
+            q: [0.0; 5],
+            n: [0.0, 1.0, 2.0, 3.0, 4.0],
+            np: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            dn: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+            count: 0,
+        }
+    }
+
+    /// 値を追加入力（NaN/∞ は無視）
+    pub fn update(&mut self, x: f32) {
+        if !x.is_finite() {
+            return;
+        }
+
+        // 最初の5サンプルはそのまま保持し、揃った時点で昇順に並べる
+        if self.count < 5 {
+            self.q[self.count as usize] = x;
+            self.count += 1;
+            if self.count == 5 {
+                sort5(&mut self.q);
+            }
+            return;
+        }
+        self.count = self.count.saturating_add(1);
+
+        // x が入るセル k を探し、端のマーカーは必要に応じて更新
+        let k = if x < self.q[0] {
+            self.q[0] = x;
+            0
+        } else if x >= self.q[4] {
+            self.q[4] = x;
+            3
+        } else {
+            let mut k = 0;
+            while k < 3 && x >= self.q[k + 1] {
+                k += 1;
+            }
+            k
+        };
+        for i in (k + 1)..5 {
+            self.n[i] += 1.0;
+        }
+        for i in 0..5 {
+            self.np[i] += self.dn[i];
+        }
+
+        // 中間マーカーの調整（放物線補間、単調性が崩れる場合は線形補間）
+        for i in 1..4 {
+            let d = self.np[i] - self.n[i];
+            if (d >= 1.0 && self.n[i + 1] - self.n[i] > 1.0)
+                || (d <= -1.0 && self.n[i - 1] - self.n[i] < -1.0)
+            {
+                let ds = if d >= 0.0 { 1.0 } else { -1.0 };
+                let qp = self.parabolic(i, ds);
+                self.q[i] = if self.q[i - 1] < qp && qp < self.q[i + 1] {
+                    qp
+                } else {
+                    self.linear(i, ds)
+                };
+                self.n[i] += ds;
+            }
+        }
+    }
+
+    /// 現在の推定値（未入力なら 0.0）
+    pub fn estimate(&self) -> f32 {
+        match self.count {
+            0 => 0.0,
+            c if c < 5 => {
+                // サンプル不足時は手元の値から最近傍順位で返す
+                let mut s = self.q;
+                let c = c as usize;
+                sort_prefix(&mut s, c);
+                let idx = libm::roundf(self.p * (c - 1) as f32) as usize;
+                s[idx.min(c - 1)]
+            }
+            _ => self.q[2],
+        }
+    }
+
+    /// 入力サンプル数
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    fn parabolic(&self, i: usize, d: f32) -> f32 {
+        let (q, n) = (&self.q, &self.n);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f32) -> f32 {
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        self.q[i] + d * (self.q[j] - self.q[i]) / (self.n[j] - self.n[i])
+    }
+}
+
+/// 先頭 len 要素の挿入ソート（要素数が小さい前提）
+fn sort_prefix(a: &mut [f32], len: usize) {
+    for i in 1..len {
+        let mut j = i;
+        while j > 0 && a[j - 1] > a[j] {
+            a.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
+
+fn sort5(a: &mut [f32; 5]) {
+    sort_prefix(a, 5);
+}
+
+/// 等幅 B バケットのヒストグラム（範囲 [min, max)）
+/// min 未満は先頭バケット、max 以上は末尾バケットへ入れる
+#[derive(Clone, Copy)]
+pub struct Histogram<const B: usize> {
+    min: f32,
+    max: f32,
+    counts: [u64; B],
+}
+
+impl<const B: usize> Histogram<B> {
+    /// 新規作成（範囲 [min, max) を B 等分）
+    pub const fn new(min: f32, max: f32) -> Self {
+        Self {
+            min,
+            max,
+            counts: [0; B],
+        }
+    }
+
+    /// 値を記録（NaN は無視）
+    pub fn record(&mut self, x: f32) {
+        if B == 0 || x.is_nan() {
+            return;
+        }
+        let idx = if x < self.min {
+            0
+        } else if x >= self.max || self.max <= self.min {
+            B - 1
+        } else {
+            let i = ((x - self.min) / (self.max - self.min) * B as f32) as usize;
+            i.min(B - 1)
+        };
+        self.counts[idx] = self.counts[idx].saturating_add(1);
+    }
+
+    /// i 番目のバケットの度数（範囲外は 0）
+    pub fn bucket(&self, i: usize) -> u64 {
+        if i < B {
+            self.counts[i]
+        } else {
+            0
+        }
+    }
+
+    /// 全度数
+    pub fn total(&self) -> u64 {
+        self.counts
+            .iter()
+            .fold(0u64, |acc, &c| acc.saturating_add(c))
+    }
+
+    /// 度数のみクリア（範囲は維持）
+    pub fn clear(&mut self) {
+        self.counts = [0; B];
+    }
+}
+
+impl<const B: usize> Default for Histogram<B> {
+    /// 範囲 [0, 1) で作成
+    fn default() -> Self {
+        Self::new(0.0, 1.0)
+    }
+}
+
+/// ヒステリシス付きの閾値判定（整数、過電流/低電圧アラート向け）
+/// 発報は trip 到達、解除は release 到達。閾値付近でのばたつきを防ぐ
+#[derive(Clone, Copy)]
+pub struct Hysteresis {
+    trip: i32,
+    release: i32,
+    rising: bool,
+    active: bool,
+}
+
+impl Hysteresis {
+    /// 上限判定: x >= trip で発報、x <= release で解除（release は trip 以下に丸める）
+    pub const fn above(trip: i32, release: i32) -> Self {
+        let release = if release > trip { trip } else { release };
+        Self {
+            trip,
+            release,
+            rising: true,
+            active: false,
+        }
+    }
+
+    /// 下限判定: x <= trip で発報、x >= release で解除（release は trip 以上に丸める）
+    pub const fn below(trip: i32, release: i32) -> Self {
+        let release = if release < trip { trip } else { release };
+        Self {
+            trip,
+            release,
+            rising: false,
+            active: false,
+        }
+    }
+
+    /// 値を入れて判定。状態が変わったときだけ新しい状態を返す
+    pub fn update(&mut self, x: i32) -> Option<bool> {
+        let next = if self.rising {
+            if self.active {
+                x > self.release
+            } else {
+                x >= self.trip
+            }
+        } else if self.active {
+            x < self.release
+        } else {
+            x <= self.trip
+        };
+        if next == self.active {
+            return None;
+        }
+        self.active = next;
+        Some(next)
+    }
+
+    /// 発報中か
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+/// 条件が連続で N 回成り立ったら発報、成り立たないサンプルが来たら解除（単発のノイズでは発報しない）
+#[derive(Clone, Copy)]
+pub struct Sustained {
+    need: u32,
+    count: u32,
+    active: bool,
+}
+
+impl Sustained {
+    /// 新規作成（need は 1 以上に丸める）
+    pub const fn new(need: u32) -> Self {
+        Self {
+            need: if need == 0 { 1 } else { need },
+            count: 0,
+            active: false,
+        }
+    }
+
+    /// 条件を入れて判定。状態が変わったときだけ新しい状態を返す
+    pub fn update(&mut self, cond: bool) -> Option<bool> {
+        self.count = if cond {
+            self.count.saturating_add(1)
+        } else {
+            0
+        };
+        let next = self.count >= self.need;
+        if next == self.active {
+            return None;
+        }
+        self.active = next;
+        Some(next)
+    }
+
+    /// 発報中か
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+/// 試験終了の検出：負荷があった後、待機（busy == false）が `hold_ms` 続いたら1回だけ発火
+/// - 一度も busy を見ていないうちは発火しない（起動直後の無負荷で要約を出さない）
+/// - 発火後は次に busy を見るまで再発火しない（負荷を再開すれば次の試験として数え直す）
+pub struct IdleTrigger {
+    hold_ms: u64,
+    /// 待機が始まった時刻 [ms]（busy 中は None）
+    idle_since_ms: Option<u64>,
+    /// busy を見てからまだ発火していないか
+    armed: bool,
+}
+
+impl IdleTrigger {
+    /// 新規作成（hold_ms は 1 以上に丸める）
+    pub const fn new(hold_ms: u64) -> Self {
+        Self {
+            hold_ms: if hold_ms == 0 { 1 } else { hold_ms },
+            idle_since_ms: None,
+            armed: false,
+        }
+    }
+
+    /// 時刻 `t_ms` のサンプルが負荷ありかを入れて判定。発火した周だけ true
+    pub fn update(&mut self, busy: bool, t_ms: u64) -> bool {
+        if busy {
+            self.idle_since_ms = None;
+            self.armed = true;
+            return false;
+        }
+        let since = *self.idle_since_ms.get_or_insert(t_ms);
+        if self.armed && t_ms.saturating_sub(since) >= self.hold_ms {
+            self.armed = false;
+            return true;
+        }
+        false
+    }
+
+    /// 待機が続いている時間 [ms]（busy 中は 0）
+    pub fn idle_ms(&self, t_ms: u64) -> u64 {
+        self.idle_since_ms
+            .map_or(0, |since| t_ms.saturating_sub(since))
+    }
+
+    /// 初期状態に戻す（時刻の起点が変わったとき用）
+    pub fn reset(&mut self) {
+        self.idle_since_ms = None;
+        self.armed = false;
+    }
+}
+
+/// 読み出しの結果の内訳（サンプル収率）
+/// - None（新データなし）が多い: 周期が変換時間より短い / タイミングの問題
+/// - エラーが多い: 配線・プルアップ・ノイズの問題
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, defmt::Format)]
+pub struct SampleCounters {
+    /// 読み出しを試みた回数（= measurements + none + errors）
+    pub attempts: u64,
+    /// 新しい計測値が得られた回数
+    pub measurements: u64,
+    /// 新データなし（変換が終わっていない）だった回数
+    pub none: u64,
+    /// 再試行しても読めなかった回数
+    pub errors: u64,
+}
+
+impl SampleCounters {
+    /// 新規作成
+    pub const fn new() -> Self {
+        Self {
+            attempts: 0,
+            measurements: 0,
+            none: 0,
+            errors: 0,
+        }
+    }
+
+    /// 計測値が得られた
+    pub fn record_measurement(&mut self) {
+        self.attempts = self.attempts.saturating_add(1);
+        self.measurements = self.measurements.saturating_add(1);
+    }
+
+    /// 新データなし
+    pub fn record_none(&mut self) {
+        self.attempts = self.attempts.saturating_add(1);
+        self.none = self.none.saturating_add(1);
+    }
+
+    /// 読み出しエラー
+    pub fn record_error(&mut self) {
+        self.attempts = self.attempts.saturating_add(1);
+        self.errors = self.errors.saturating_add(1);
+    }
+
+    /// 収率（%）: 100 * measurements / attempts。試行 0 なら 0
+    pub fn yield_percent(&self) -> f32 {
+        if self.attempts == 0 {
+            return 0.0;
+        }
+        (100.0 * self.measurements as f64 / self.attempts as f64) as f32
+    }
+
+    /// ゼロに戻す
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// 積算器（固定小数）：
+/// - 累計電荷: µA·s（u128）。放電（I>0）と充電（I<0）を別々に積算
+/// - 累計エネルギー: µW·s（u128。2 kW が続いても飽和まで 10^21 年以上。飽和したら `saturated()` が立つ）
+/// - 稼働時間: ms（u64）、うちカットオフ後の電流が流れていた時間（デューティ比）
+pub struct Accumulators {
+    /// 放電方向（I>0、VIN+ → VIN−）の電荷
+    charge_out_uas: u128,
+    /// 充電方向（I<0）の電荷（絶対値）
+    charge_in_uas: u128,
+    energy_uws: u128,
+    pub uptime_ms: u64,
+    /// カットオフ適用後の電流が 0 でなかった時間 [ms]（デューティ比の分子）
+    active_ms: u64,
+    /// 微小電流のカットオフ（mA）。非アクティブ時は |I| >= cutoff でアクティブへ
+    pub current_cutoff_ma: u32,
+    /// カットオフの解除しきい値（mA）。アクティブ時は |I| < release で0扱いへ戻る
+    /// `current_cutoff_ma` と同じ値ならヒステリシスなし
+    pub cutoff_release_ma: u32,
+    /// 微小電力のカットオフ（mW）。|P| < cutoff の区間はエネルギーを積算しない（0 = 無効、ヒステリシスなし）
+    /// 電流のカットオフとは独立（電荷/デューティ比には効かない）
+    pub power_cutoff_mw: u32,
+    /// カットオフのヒステリシス状態（true: 電流を積算する）
+    active: bool,
+    /// 台形積分用の前回値（カットオフ適用後の mA / mW）
+    prev: Option<(f32, f32)>,
+    /// 電池容量（µW·s）。SoC の基準
+    capacity_uws: u128,
+    /// 計測開始時の電池の電荷（µA·s）。クーロンカウンタ式 SoC の基準（0 なら未設定）
+    initial_charge_uas: u128,
+    /// セッション中の最大電力（|P|, mW）
+    peak_power_mw: f32,
+    /// セッション中の最大電流（|I|, mA）
+    peak_current_ma: f32,
+    /// いずれかの積算値が上限で頭打ちになったか
+    saturated: bool,
+}
+
+/// 単三電池（2.5 Wh）の容量 [µW·s]（2.5 Wh × 3.6e9）
+pub const AA_CAPACITY_UWS: u128 = 9_000_000_000;
+
+impl Accumulators {
+    /// 新規作成（容量は単三 2.5 Wh。変更は `with_capacity_uws` / `set_capacity_uws`）
+    pub const fn new(cutoff_ma: u32) -> Self {
+        Self {
+            charge_out_uas: 0,
+            charge_in_uas: 0,
+            energy_uws: 0,
+            uptime_ms: 0,
+            active_ms: 0,
+            current_cutoff_ma: cutoff_ma,
+            cutoff_release_ma: cutoff_ma,
+            power_cutoff_mw: 0,
+            active: false,
+            prev: None,
+            capacity_uws: AA_CAPACITY_UWS,
+            initial_charge_uas: 0,
+            peak_power_mw: 0.0,
+            peak_current_ma: 0.0,
+            saturated: false,
+        }
+    }
+
+    /// カットオフ解除しきい値（mA）を指定して返す（ビルダー）。cutoff より大きい値は cutoff に丸める
+    pub const fn with_cutoff_release_ma(mut self, release_ma: u32) -> Self {
+        self.cutoff_release_ma = if release_ma > self.current_cutoff_ma {
+            self.current_cutoff_ma
+        } else {
+            release_ma
+        };
+        self
+    }
+
+    /// 微小電力のカットオフ（mW）を指定して返す（ビルダー）。0 で無効
+    pub const fn with_power_cutoff_mw(mut self, cutoff_mw: u32) -> Self {
+        self.power_cutoff_mw = cutoff_mw;
+        self
+    }
+
+    /// 電池容量（µW·s）を指定して返す（ビルダー）
+    pub const fn with_capacity_uws(mut self, capacity_uws: u128) -> Self {
+        self.capacity_uws = capacity_uws;
+        self
+    }
+
+    /// 電池容量（µW·s）を途中で変更
+    pub fn set_capacity_uws(&mut self, capacity_uws: u128) {
+        self.capacity_uws = capacity_uws;
+    }
+
+    /// 電池容量（µW·s）
+    pub fn capacity_uws(&self) -> u128 {
+        self.capacity_uws
+    }
+
+    /// 計測開始時の電荷（mAh）を指定して返す（ビルダー）。`remaining_mah` / `soc_coulomb_percent` の基準
+    pub const fn with_initial_charge_mah(mut self, mah: u32) -> Self {
+        self.initial_charge_uas = mah as u128 * 3_600_000; // 1 mAh = 3_600_000 µA·s
+        self
+    }
+
+    /// 計測開始時の電荷（mAh）を途中で変更（満充電の電池に差し替えたときなど）
+    pub fn set_initial_charge_mah(&mut self, mah: u32) {
+        self.initial_charge_uas = mah as u128 * 3_600_000;
+    }
+
+    /// 残りの電荷（mAh）: 初期電荷 − 正味の放電電荷（充電分は戻す）。使い切ると負
+    pub fn remaining_mah(&self) -> f32 {
+        let remaining = self.initial_charge_uas as f64 - self.net_charge_uas() as f64;
+        (remaining / 3_600_000.0) as f32
+    }
+
+    /// クーロンカウンタ式の残量（%）: 100 * 残り電荷 / 初期電荷、[0, 100] にクランプ。初期電荷 0 なら 0
+    /// 電圧に依らないので、定電圧負荷ではエネルギー基準の `soc_percent` より安定する
+    pub fn soc_coulomb_percent(&self) -> f32 {
+        if self.initial_charge_uas == 0 {
+            return 0.0;
+        }
+        let remaining = self.initial_charge_uas as f64 - self.net_charge_uas() as f64;
+        let soc = (100.0 * remaining / self.initial_charge_uas as f64) as f32;
+        soc.clamp(0.0, 100.0)
+    }
+
+    /// 電荷・エネルギー・稼働時間をゼロに戻す（カットオフ設定は維持）
+    pub fn reset(&mut self) {
+        self.charge_out_uas = 0;
+        self.charge_in_uas = 0;
+        self.energy_uws = 0;
+        self.uptime_ms = 0;
+        self.active_ms = 0;
+        self.prev = None;
+        self.peak_power_mw = 0.0;
+        self.peak_current_ma = 0.0;
+        self.saturated = false;
+        self.active = false;
+    }
+
+    /// 生の積算値 (放電電荷[µA·s], エネルギー[µW·s], 稼働時間[ms]) を返す
+    pub fn snapshot(&self) -> (u128, u128, u64) {
+        (self.charge_out_uas, self.energy_uws, self.uptime_ms)
+    }
+
+    /// 正味電荷（放電 − 充電, µA·s）。保存用に整数のまま返す
+    pub fn net_charge_uas(&self) -> i128 {
+        self.charge_out_uas as i128 - self.charge_in_uas as i128
+    }
+
+    /// 保存しておいた正味電荷[µA·s]とエネルギー[µW·s]から積算を再開する（稼働時間・ピークは触らない）
+    pub fn restore(&mut self, net_charge_uas: i64, energy_uws: u128) {
+        if net_charge_uas >= 0 {
+            self.charge_out_uas = net_charge_uas as u128;
+            self.charge_in_uas = 0;
+        } else {
+            self.charge_out_uas = 0;
+            self.charge_in_uas = net_charge_uas.unsigned_abs() as u128;
+        }
+        self.energy_uws = energy_uws;
+    }
+
+    /// 積算更新（矩形近似：今回値を区間一定とみなす）
+    /// v_v: V, i_ma: mA, p_mw: mW, dt_ms: 経過時間[ms]
+    pub fn update(&mut self, _v_v: f32, i_ma: f32, p_mw: f32, dt_ms: u32) {
+        self.track_peaks(i_ma, p_mw);
+        let i_ma_eff = self.apply_cutoff(i_ma);
+        let p_mw_eff = self.apply_power_cutoff(p_mw);
+        self.integrate(i_ma_eff, p_mw_eff, dt_ms);
+        self.prev = Some((i_ma_eff, p_mw_eff));
+    }
+
+    /// 積算更新（台形則：0.5*(前回値+今回値)*dt）
+    /// 負荷が変化している区間での矩形近似の偏りを抑える。前回値が無い初回は矩形近似。
+    pub fn update_trapezoidal(&mut self, _v_v: f32, i_ma: f32, p_mw: f32, dt_ms: u32) {
+        self.track_peaks(i_ma, p_mw);
+        let i_ma_eff = self.apply_cutoff(i_ma);
+        let p_mw_eff = self.apply_power_cutoff(p_mw);
+        let (i_avg, p_avg) = match self.prev {
+            Some((pi, pp)) => (0.5 * (pi + i_ma_eff), 0.5 * (pp + p_mw_eff)),
+            None => (i_ma_eff, p_mw_eff),
+        };
+        self.integrate(i_avg, p_avg, dt_ms);
+        self.prev = Some((i_ma_eff, p_mw_eff));
+    }
+
+    /// 積算値が一度でも飽和したか（true なら長時間計測の合計値は信頼できない）
+    pub fn saturated(&self) -> bool {
+        self.saturated
+    }
+
+    /// 最大電力（|P|, mW）
+    pub fn peak_power_mw(&self) -> f32 {
+        self.peak_power_mw
+    }
+
+    /// 最大電流（|I|, mA）
+    pub fn peak_current_ma(&self) -> f32 {
+        self.peak_current_ma
+    }
+
+    /// ピーク値の更新（カットオフ前の生の読み取り値を使用）
+    fn track_peaks(&mut self, i_ma: f32, p_mw: f32) {
+        let i_abs = i_ma.abs();
+        let p_abs = p_mw.abs();
+        if i_abs.is_finite() && i_abs > self.peak_current_ma {
+            self.peak_current_ma = i_abs;
+        }
+        if p_abs.is_finite() && p_abs > self.peak_power_mw {
+            self.peak_power_mw = p_abs;
+        }
+    }
+
+    /// カットオフのヒステリシス状態（true: 電流を積算中）
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// カットオフ適用後の電流が流れていた時間 [ms]
+    pub fn active_ms(&self) -> u64 {
+        self.active_ms
+    }
+
+    /// デューティ比 = active_ms / uptime_ms（0.0..=1.0、稼働時間 0 なら 0.0）
+    /// カットオフ 0（`new(0)`）では電流 0 ちょうど以外が全部アクティブなので、ほぼ常に 1.0。意味を持つのはカットオフを設定したときだけ
+    pub fn duty_cycle(&self) -> f32 {
+        if self.uptime_ms == 0 {
+            return 0.0;
+        }
+        (self.active_ms as f64 / self.uptime_ms as f64) as f32
+    }
+
+    /// 微小電流カットオフ（ヒステリシス付き）
+    /// しきい値付近で積算/0扱いが毎サンプル入れ替わるのを防ぐ
+    fn apply_cutoff(&mut self, i_ma: f32) -> f32 {
+        let i_abs = i_ma.abs();
+        if self.active {
+            if i_abs < self.cutoff_release_ma as f32 {
+                self.active = false;
+            }
+        } else if i_abs >= self.current_cutoff_ma as f32 {
+            self.active = true;
+        }
+        if self.active {
+            i_ma
+        } else {
+            0.0
+        }
+    }
+
+    /// 微小電力カットオフ（|P| < power_cutoff_mw なら 0 として積算）。待機時のセンサノイズでエネルギーが増えないように
+    fn apply_power_cutoff(&self, p_mw: f32) -> f32 {
+        if p_mw.abs() < self.power_cutoff_mw as f32 {
+            0.0
+        } else {
+            p_mw
+        }
+    }
+
+    /// dt 区間を i_ma / p_mw 一定として加算
+    fn integrate(&mut self, i_ma: f32, p_mw: f32, dt_ms: u32) {
+        match self.uptime_ms.checked_add(dt_ms as u64) {
+            Some(t) => self.uptime_ms = t,
+            None => {
+                self.uptime_ms = u64::MAX;
+                self.saturated = true;
+            }
+        }
+        if i_ma != 0.0 {
+            self.active_ms = self.active_ms.saturating_add(dt_ms as u64);
+        }
+
+        // 電荷: µA·s = (i[mA]*1000)[µA] * (dt[ms]/1000)[s]
+        //      = i[mA] * dt[ms]
+        // 単位合わせ：i[mA]*dt[ms] = (mA·ms) = µA·s
+        // 符号で放電/充電に振り分ける
+        let dq_uas = (i_ma as f64) * (dt_ms as f64);
+        if dq_uas.is_finite() {
+            if dq_uas >= 0.0 {
+                self.saturated |= add_saturating(&mut self.charge_out_uas, dq_uas as u128);
+            } else {
+                self.saturated |= add_saturating(&mut self.charge_in_uas, (-dq_uas) as u128);
+            }
+        }
+
+        // エネルギー: µW·s = (p[mW]*1000)[µW] * (dt[ms]/1000)[s]
+        //           = p[mW] * dt[ms]
+        let de_uws = (p_mw as f64) * (dt_ms as f64);
+        if de_uws.is_finite() && de_uws >= 0.0 {
+            self.saturated |= add_saturating(&mut self.energy_uws, de_uws as u128);
+        }
+    }
+
+    /// 累計電荷の読み出し（mAh）。互換のため正味値を返す
+    pub fn readout_charge_mah(&self) -> f32 {
+        self.readout_net_charge_mah()
+    }
+
+    /// 正味電荷（放電 − 充電, mAh）。充電超過なら負
+    pub fn readout_net_charge_mah(&self) -> f32 {
+        // 1 mAh = 3_600_000 µA·s
+        let net = self.charge_out_uas as f64 - self.charge_in_uas as f64;
+        (net / 3_600_000.0) as f32
+    }
+
+    /// 総電荷（放電 + 充電, mAh）
+    pub fn readout_gross_charge_mah(&self) -> f32 {
+        let gross = self.charge_out_uas.saturating_add(self.charge_in_uas);
+        (gross as f64 / 3_600_000.0) as f32
+    }
+
+    /// 残量推定（%）: 100 * (1 - E / 容量)、[0, 100] にクランプ。容量 0 なら 0
+    pub fn soc_percent(&self) -> f32 {
+        if self.capacity_uws == 0 {
+            return 0.0;
+        }
+        let used = self.energy_uws as f64 / self.capacity_uws as f64;
+        let soc = (100.0 * (1.0 - used)) as f32;
+        soc.clamp(0.0, 100.0)
+    }
+
+    /// 容量に対する消費割合（%）: 100 * E / 容量。100% を超えても丸めない。容量 0 なら 0
+    pub fn consumed_percent(&self) -> f32 {
+        percent_of_capacity(self.energy_uws, self.capacity_uws)
+    }
+
+    /// 残エネルギー（µW·s）: 容量 − 累計エネルギー（使い切ったら 0）
+    pub fn remaining_uws(&self) -> u128 {
+        self.capacity_uws.saturating_sub(self.energy_uws)
+    }
+
+    /// 残り時間の推定（ms）: 残エネルギー `capacity_remaining_uws`[µW·s] / 平均電力 `avg_power_mw`[mW]
+    /// 平均電力は呼び出し側の平均化方式（Ewma 等）に任せる。電力がほぼ 0 なら u64::MAX
+    /// 残エネルギーを外から渡すので、途中まで充電した電池など容量どおりでない場合にも使える
+    /// （設定した容量から求めるなら `time_to_empty_from_capacity_ms`）
+    pub fn time_to_empty_ms(&self, avg_power_mw: f32, capacity_remaining_uws: u128) -> u64 {
+        if !avg_power_mw.is_finite() || avg_power_mw <= 1e-6 {
+            return u64::MAX;
+        }
+        // µW·s / mW = ms
+        let ms = capacity_remaining_uws as f64 / avg_power_mw as f64;
+        if ms >= u64::MAX as f64 {
+            u64::MAX
+        } else {
+            ms as u64
+        }
+    }
+
+    /// 設定した容量の残り（`remaining_uws`）での残り時間の推定（ms）
+    pub fn time_to_empty_from_capacity_ms(&self, avg_power_mw: f32) -> u64 {
+        self.time_to_empty_ms(avg_power_mw, self.remaining_uws())
+    }
+
+    /// 累計エネルギーの読み出し（mWh, Wh）
+    pub fn readout_energy(&self) -> (f32, f32) {
+        // 1 mWh = 3_600_000 µW·s, 1 Wh = 1000 mWh
+        let mwh = (self.energy_uws as f64 / 3_600_000.0) as f32;
+        let wh = mwh / 1000.0;
+        (mwh, wh)
+    }
+
+    /// 累計エネルギーの読み出し（J）。1 µW·s = 1 µJ。長時間でも分解能を保つよう f64
+    pub fn readout_energy_joules(&self) -> f64 {
+        self.energy_uws as f64 / 1_000_000.0
+    }
+
+    /// 表示単位での読み出し（mAh は電荷の積算器から正味電荷、それ以外はエネルギーから）
+    pub fn readout_in(&self, unit: DisplayUnit) -> f32 {
+        match unit {
+            DisplayUnit::MilliWattHour => self.readout_energy().0,
+            DisplayUnit::WattHour => self.readout_energy().1,
+            DisplayUnit::Joule => self.readout_energy_joules() as f32,
+            DisplayUnit::MilliAmpHour => self.readout_net_charge_mah(),
+        }
+    }
+
+    /// 読み出しをまとめた要約（1回のログで出せるように）
+    /// 平均電力は E[µW·s] / 稼働時間[ms] = mW。稼働時間 0 なら 0
+    pub fn summary(&self) -> Summary {
+        let (energy_mwh, energy_wh) = self.readout_energy();
+        let avg_power_mw = if self.uptime_ms == 0 {
+            0.0
+        } else {
+            (self.energy_uws as f64 / self.uptime_ms as f64) as f32
+        };
+        Summary {
+            charge_mah: self.readout_net_charge_mah(),
+            energy_mwh,
+            energy_wh,
+            uptime_ms: self.uptime_ms,
+            avg_power_mw,
+            duty_cycle: self.duty_cycle(),
+        }
+    }
+}
+
+/// `Accumulators::summary` の結果（`info!("{}", summary)` でそのまま出せる）
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub struct Summary {
+    /// 正味電荷 [mAh]
+    pub charge_mah: f32,
+    /// 累計エネルギー [mWh]
+    pub energy_mwh: f32,
+    /// 累計エネルギー [Wh]
+    pub energy_wh: f32,
+    /// 稼働時間 [ms]
+    pub uptime_ms: u64,
+    /// 平均電力 [mW]
+    pub avg_power_mw: f32,
+    /// デューティ比（電流が流れていた時間 / 稼働時間、0.0..=1.0）
+    pub duty_cycle: f32,
+}
+
+/// 累計値の表示単位（`Accumulators::readout_in` で換算）
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum DisplayUnit {
+    /// エネルギー [mWh]
+    MilliWattHour,
+    /// エネルギー [Wh]
+    WattHour,
+    /// エネルギー [J]
+    Joule,
+    /// 正味電荷 [mAh]
+    MilliAmpHour,
+}
+
+impl DisplayUnit {
+    /// 単位の表記
+    pub const fn label(self) -> &'static str {
+        match self {
+            DisplayUnit::MilliWattHour => "mWh",
+            DisplayUnit::WattHour => "Wh",
+            DisplayUnit::Joule => "J",
+            DisplayUnit::MilliAmpHour => "mAh",
+        }
+    }
+}
+
+/// 飽和加算。実際に上限で頭打ちになった（加算値が捨てられた）場合 true
+fn add_saturating(acc: &mut u128, x: u128) -> bool {
+    match acc.checked_add(x) {
+        Some(v) => {
+            *acc = v;
+            false
+        }
+        None => {
+            *acc = u128::MAX;
+            true
+        }
+    }
+}
+
+/// `acc += x * n` の飽和版。積か和が i64 に収まらず頭打ちにした場合 true
+fn add_mul_saturating(acc: &mut i64, x: i64, n: i64) -> bool {
+    match x.checked_mul(n).and_then(|d| acc.checked_add(d)) {
+        Some(v) => {
+            *acc = v;
+            false
+        }
+        None => {
+            *acc = acc.saturating_add(x.saturating_mul(n));
+            true
+        }
+    }
+}
+
+/// 区間 [from_ms, to_ms) のうち、直近サンプルがまだ有効（`valid_until_ms` まで）な長さ [ms]
+/// 読み出しが途切れている間に古い値で積算し続けないよう、積算に使う dt をこれで切り詰める
+pub fn held_ms(from_ms: u64, to_ms: u64, valid_until_ms: u64) -> u64 {
+    core::cmp::min(to_ms, valid_until_ms).saturating_sub(from_ms)
+}
+
+/// `held_ms` に有効区間の始まり（`valid_from_ms`）を加えた版
+/// 起動直後や途切れた後の最初のサンプルは、読み出した時刻より前の区間に使わない
+pub fn held_ms_from(from_ms: u64, to_ms: u64, valid_from_ms: u64, valid_until_ms: u64) -> u64 {
+    held_ms(
+        core::cmp::max(from_ms, valid_from_ms),
+        to_ms,
+        valid_until_ms,
+    )
+}
+
+/// 1 周分の dt [ms] を `max_ms` で頭打ちにする。切り詰めたら true
+/// タイマの巻き戻り/リセットで異常に大きな dt が来ても、積算に一度に入る量を抑える
+pub fn clamp_dt_ms(dt_ms: u64, max_ms: u64) -> (u64, bool) {
+    if dt_ms > max_ms {
+        (max_ms, true)
+    } else {
+        (dt_ms, false)
+    }
+}
+
+/// 集計窓ごとの結果（時間重み付き平均と消費エネルギー）
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct MinuteReport {
+    /// 何窓目（1始まり。既定の 1 分窓なら何分目）
+    pub index: u64,
+    /// 時間重み付き平均電圧 [mV]（切り捨て）
+    pub avg_v_mv: i32,
+    /// 時間重み付き平均電流 [µA]（切り捨て）
+    pub avg_i_ua: i32,
+    /// その窓のエネルギー [µW·ms]
+    pub energy_uwms: i64,
+    /// 有効なサンプルで積算できた時間 [ms]（理想は窓の長さ）
+    pub covered_ms: u64,
+    /// この窓のいずれかの積算値が i64 の上限で頭打ちになったか（true なら energy_uwms と平均は信頼できない）
+    pub saturated: bool,
+}
+
+/// 集計窓（既定 1 分）区切りの時間重み付き積算（矩形近似：直近サンプルの値を区間一定とみなす）
+/// 窓の境界をまたぐ区間は境界で分割し、前後の窓にそれぞれ按分する
+pub struct MinuteAggregator {
+    /// 窓の長さ [ms]（> 0）
+    window_ms: u64,
+    /// 積算済みの時刻 [ms]（起点 0）
+    last_ms: u64,
+    /// 直近サンプルを積算に使ってよい区間 [ms]（`held_ms_from` 参照）
+    valid_from_ms: u64,
+    valid_until_ms: u64,
+    v_mv_ms: i64,
+    i_ua_ms: i64,
+    energy_uwms: i64,
+    duration_ms: u64,
+    count: u64,
+    /// 今の窓で積算値が飽和したか
+    saturated: bool,
+}
+
+impl MinuteAggregator {
+    /// 既定の窓の長さ [ms]（1 分）
+    pub const DEFAULT_WINDOW_MS: u64 = 60_000;
+
+    /// 新規作成（1 分窓、時刻 0 から。有効期限 `u64::MAX` = 値をずっと有効とみなす）
+    pub const fn new() -> Self {
+        Self {
+            window_ms: Self::DEFAULT_WINDOW_MS,
+            last_ms: 0,
+            valid_from_ms: 0,
+            valid_until_ms: u64::MAX,
+            v_mv_ms: 0,
+            i_ua_ms: 0,
+            energy_uwms: 0,
+            duration_ms: 0,
+            count: 0,
+            saturated: false,
+        }
+    }
+
+    /// 窓の長さ [ms] を指定して返す（ビルダー）。0 は 1 に丸める
+    pub const fn with_window_ms(mut self, window_ms: u64) -> Self {
+        self.window_ms = if window_ms == 0 { 1 } else { window_ms };
+        self
+    }
+
+    /// 窓の長さ [ms]
+    pub fn window_ms(&self) -> u64 {
+        self.window_ms
+    }
+
+    /// 電力 `power_uw` [µW] が続いたとき、窓のエネルギー [µW·ms, i64] が飽和せずに済む最長の窓 [ms]
+    /// 例: 2 W で約 146 年、入力の上限 i32::MAX µW（約 2.1 kW）でも u32::MAX ms（約 49 日）より長い
+    /// 窓ごとに 0 に戻すので、計測の総時間ではなく窓の長さだけが効く（累計は u128 の Accumulators）
+    pub const fn max_window_ms(power_uw: i64) -> u64 {
+        if power_uw == 0 {
+            return u64::MAX;
+        }
+        i64::MAX.unsigned_abs() / power_uw.unsigned_abs()
+    }
+
+    /// 時刻 0・窓カウント 0 に戻す（窓の長さは維持）
+    pub fn reset(&mut self) {
+        *self = Self::new().with_window_ms(self.window_ms);
+    }
+
+    /// 直近サンプルの有効期限 [ms] を設定。これ以降の区間は時間だけ進め、平均にも含めない
+    pub fn set_valid_until(&mut self, valid_until_ms: u64) {
+        self.valid_until_ms = valid_until_ms;
+    }
+
+    /// 直近サンプルが有効になった時刻 [ms] を設定。これより前の区間は時間だけ進め、平均にも含めない
+    pub fn set_valid_from(&mut self, valid_from_ms: u64) {
+        self.valid_from_ms = valid_from_ms;
+    }
+
+    /// `now_ms` まで直近の V/I/P で積算を進める。窓の境界に達したらそこで止めて集計を返す
+    /// 一度に複数の窓をまたぐ場合があるので、`None` になるまで同じ `now_ms` で呼び続ける
+    pub fn advance(
+        &mut self,
+        now_ms: u64,
+        last_v_mv: i32,
+        last_i_ua: i32,
+        last_p_uw: i64,
+    ) -> Option<MinuteReport> {
+        while self.last_ms < now_ms {
+            let next_boundary = (self.last_ms / self.window_ms + 1) * self.window_ms;
+            let step_end = core::cmp::min(now_ms, next_boundary);
+            let held = held_ms_from(
+                self.last_ms,
+                step_end,
+                self.valid_from_ms,
+                self.valid_until_ms,
+            );
+            let held_i64 = held as i64;
+            self.saturated |= add_mul_saturating(&mut self.energy_uwms, last_p_uw, held_i64);
+            self.saturated |= add_mul_saturating(&mut self.v_mv_ms, last_v_mv as i64, held_i64);
+            self.saturated |= add_mul_saturating(&mut self.i_ua_ms, last_i_ua as i64, held_i64);
+            self.duration_ms = self.duration_ms.saturating_add(held);
+            self.last_ms = step_end;
+
+            if self.last_ms == next_boundary {
+                self.count = self.count.saturating_add(1);
+                let avg = |sum: i64| {
+                    if self.duration_ms > 0 {
+                        (sum / self.duration_ms as i64) as i32
+                    } else {
+                        0
+                    }
+                };
+                let report = MinuteReport {
+                    index: self.count,
+                    avg_v_mv: avg(self.v_mv_ms),
+                    avg_i_ua: avg(self.i_ua_ms),
+                    energy_uwms: self.energy_uwms,
+                    covered_ms: self.duration_ms,
+                    saturated: self.saturated,
+                };
+                self.v_mv_ms = 0;
+                self.i_ua_ms = 0;
+                self.energy_uwms = 0;
+                self.duration_ms = 0;
+                self.saturated = false;
+                return Some(report);
+            }
+        }
+        None
+    }
+}
+
+impl Default for MinuteAggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 待機中に計測周期を延ばす状態機械
+/// - 静かな（電流が閾値未満の）サンプルが `quiet_samples` 回続くたびに周期を倍（上限 max_ms）
+/// - 1 回でも動きがあれば即座に基準周期へ戻す
+#[derive(Clone, Copy)]
+pub struct AdaptivePeriod {
+    base_ms: u32,
+    max_ms: u32,
+    period_ms: u32,
+    quiet_samples: u32,
+    quiet: u32,
+}
+
+impl AdaptivePeriod {
+    /// 新規作成（基準周期 base_ms から開始。max_ms は base_ms 以上に丸める、quiet_samples は 1 以上）
+    pub const fn new(base_ms: u32, max_ms: u32, quiet_samples: u32) -> Self {
+        let max_ms = if max_ms < base_ms { base_ms } else { max_ms };
+        let quiet_samples = if quiet_samples == 0 { 1 } else { quiet_samples };
+        Self {
+            base_ms,
+            max_ms,
+            period_ms: base_ms,
+            quiet_samples,
+            quiet: 0,
+        }
+    }
+
+    /// 基準周期を変える（周期変更コマンド用）。今の周期も基準に戻す
+    pub fn set_base(&mut self, base_ms: u32) {
+        *self = Self::new(base_ms, self.max_ms, self.quiet_samples);
+    }
+
+    /// 現在の周期 [ms]
+    pub fn period_ms(&self) -> u32 {
+        self.period_ms
+    }
+
+    /// サンプルが動きありか（閾値以上か）を入れる。周期が変わったときだけ新しい周期を返す
+    pub fn update(&mut self, active: bool) -> Option<u32> {
+        let next = if active {
+            self.quiet = 0;
+            self.base_ms
+        } else {
+            self.quiet += 1;
+            if self.quiet < self.quiet_samples {
+                return None;
+            }
+            self.quiet = 0;
+            self.period_ms.saturating_mul(2).min(self.max_ms)
+        };
+        if next == self.period_ms {
+            return None;
+        }
+        self.period_ms = next;
+        Some(next)
+    }
+}
+
+/// 電力変換効率 [%] = P_out / P_in × 100（0..=100 に飽和）
+/// 例: p_in=1000, p_out=850 → 85.0。P_in が 0 以下なら 0.0
+pub fn efficiency_percent(p_in_uw: i64, p_out_uw: i64) -> f32 {
+    if p_in_uw <= 0 {
+        return 0.0;
+    }
+    let eff = p_out_uw as f32 / p_in_uw as f32 * 100.0;
+    eff.clamp(0.0, 100.0)
+}
+
+/// 見かけの負荷抵抗 [Ω] = V / |I|。例: 5000 mV, 1_000_000 µA → 5.0
+/// 電流が 0 なら f32::INFINITY（微小電流でのばらつきは呼び出し側で電流の下限を設けて避ける）
+pub fn load_ohms(v_mv: i32, i_ua: i32) -> f32 {
+    if i_ua == 0 {
+        return f32::INFINITY;
+    }
+    // mV / µA = kΩ
+    (v_mv as f64 * 1000.0 / i_ua.unsigned_abs() as f64) as f32
+}
+
+/// 起点 `epoch_s`（その日の 0 時からの秒）から `elapsed_s` 秒後の時刻（時, 分, 秒）。24時間で折り返す
+pub fn wall_clock_hms(epoch_s: u32, elapsed_s: u64) -> (u8, u8, u8) {
+    let sod = (epoch_s as u64 + elapsed_s) % 86_400;
+    (
+        (sod / 3600) as u8,
+        ((sod % 3600) / 60) as u8,
+        (sod % 60) as u8,
+    )
+}
+
+/// 電池本数換算（AA/AAA）。E_Wh / 代表容量[Wh]
+pub fn battery_equiv(wh: f32, e_aa_wh: f32, e_aaa_wh: f32) -> (f32, f32) {
+    let aa = if e_aa_wh > 0.0 { wh / e_aa_wh } else { 0.0 };
+    let aaa = if e_aaa_wh > 0.0 { wh / e_aaa_wh } else { 0.0 };
+    (aa, aaa)
+}
+
+/// 電池の種類（代表的な容量で本数換算する）
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum BatteryType {
+    /// アルカリ単三（約 3.9 Wh）
+    AlkalineAa,
+    /// アルカリ単四（約 1.8 Wh）
+    AlkalineAaa,
+    /// ニッケル水素単三（約 2.5 Wh）
+    NimhAa,
+    /// ニッケル水素単四（約 1.0 Wh）
+    NimhAaa,
+    /// リチウム単三（約 4.5 Wh）
+    LithiumAa,
+    /// コイン電池 CR2032（約 0.65 Wh）
+    Cr2032,
+}
+
+impl BatteryType {
+    /// 代表容量 [Wh]（公称電圧 × 公称容量の目安）
+    pub const fn capacity_wh(self) -> f32 {
+        match self {
+            BatteryType::AlkalineAa => 3.9,
+            BatteryType::AlkalineAaa => 1.8,
+            BatteryType::NimhAa => 2.5,
+            BatteryType::NimhAaa => 1.0,
+            BatteryType::LithiumAa => 4.5,
+            BatteryType::Cr2032 => 0.65,
+        }
+    }
+
+    /// 表示用の短い名前（`AA=000.08%` の `AA` など）
+    pub const fn label(self) -> &'static str {
+        match self {
+            BatteryType::AlkalineAa | BatteryType::NimhAa | BatteryType::LithiumAa => "AA",
+            BatteryType::AlkalineAaa | BatteryType::NimhAaa => "AAA",
+            BatteryType::Cr2032 => "CR2032",
+        }
+    }
+}
+
+/// 容量に対する消費割合（%）: 100 * energy / capacity（どちらも µW·s）。100% を超えても丸めない。容量 0 なら 0
+/// 累計（`Accumulators::consumed_percent`）と集計窓の % を同じ式で出すため
+pub fn percent_of_capacity(energy_uws: u128, capacity_uws: u128) -> f32 {
+    if capacity_uws == 0 {
+        return 0.0;
+    }
+    (100.0 * energy_uws as f64 / capacity_uws as f64) as f32
+}
+
+/// 低温時の電池容量の目減り（区分線形）。戻り値は base_wh × 係数 [Wh]
+/// 係数: 20 °C 以上で 100%、0 °C で 70%、-20 °C 以下で 40%（間は直線補間）
+/// アルカリ/ニッケル水素のおおまかな傾向。NaN は 20 °C 扱い
+pub fn derate_capacity(base_wh: f32, temp_c: f32) -> f32 {
+    // (温度 [°C], 係数) を昇順に
+    const CURVE: [(f32, f32); 3] = [(-20.0, 0.40), (0.0, 0.70), (20.0, 1.00)];
+    let t = if temp_c.is_nan() { CURVE[2].0 } else { temp_c };
+    let factor = if t <= CURVE[0].0 {
+        CURVE[0].1
+    } else if t >= CURVE[CURVE.len() - 1].0 {
+        CURVE[CURVE.len() - 1].1
+    } else {
+        let mut f = CURVE[0].1;
+        for w in CURVE.windows(2) {
+            let ((t0, f0), (t1, f1)) = (w[0], w[1]);
+            if t <= t1 {
+                f = f0 + (f1 - f0) * (t - t0) / (t1 - t0);
+                break;
+            }
+        }
+        f
+    };
+    base_wh * factor
+}
+
+/// 電池本数換算（種類指定）。E_Wh / 代表容量[Wh]。容量が 0 以下なら 0
+pub fn battery_equiv_for(wh: f32, battery: BatteryType) -> f32 {
+    let cap = battery.capacity_wh();
+    if cap > 0.0 {
+        wh / cap
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_clock_hms_adds_elapsed_and_wraps_at_midnight() {
+        assert_eq!(wall_clock_hms(0, 0), (0, 0, 0));
+        assert_eq!(wall_clock_hms(12 * 3600 + 34 * 60 + 56, 4), (12, 35, 0));
+        assert_eq!(wall_clock_hms(23 * 3600 + 59 * 60 + 59, 1), (0, 0, 0));
+        assert_eq!(wall_clock_hms(3600, 86_400 * 3 + 61), (1, 1, 1));
+    }
+
+    #[test]
+    fn running_stats_reset_clears_variance_and_stddev() {
+        let mut st = RunningStats::new();
+        for x in [1.0, 2.0, 4.0, 8.0] {
+            st.update(x);
+        }
+        assert!(st.variance() > 0.0);
+        st.reset();
+        assert_eq!(st.n, 0);
+        assert_eq!(st.variance(), 0.0);
+        assert_eq!(st.stddev(), 0.0);
+        assert_eq!((st.min, st.max), (f32::INFINITY, f32::NEG_INFINITY));
+    }
+
+    fn close(a: f32, b: f32, tol: f32) -> bool {
+        (a - b).abs() <= tol
+    }
+
+    #[test]
+    fn running_stats_merge_of_halves_matches_single_pass() {
+        let xs = [3.0, 1.5, 4.0, 1.0, 5.5, 9.0, 2.5, 6.0, 5.0, 3.5];
+        let mut all = RunningStats::new();
+        for &x in &xs {
+            all.update(x);
+        }
+        let (mut a, mut b) = (RunningStats::new(), RunningStats::new());
+        for &x in &xs[..4] {
+            a.update(x);
+        }
+        for &x in &xs[4..] {
+            b.update(x);
+        }
+        a.merge(&b);
+        assert_eq!(a.n, all.n);
+        assert!(close(a.mean, all.mean, 1e-5));
+        assert!(close(a.variance(), all.variance(), 1e-4));
+        assert_eq!((a.min, a.max), (1.0, 9.0));
+    }
+
+    #[test]
+    fn running_stats_merge_with_empty_is_identity() {
+        let mut a = RunningStats::new();
+        for x in [1.0, 2.0, 3.0] {
+            a.update(x);
+        }
+        let empty = RunningStats::new();
+        let mut b = empty;
+        b.merge(&a);
+        a.merge(&empty);
+        assert_eq!((a.n, a.mean, a.variance()), (b.n, b.mean, b.variance()));
+    }
+
+    #[test]
+    fn ewma_converges_to_constant_input() {
+        let mut e = Ewma::new(0.2);
+        e.update(0.0);
+        for _ in 0..200 {
+            e.update(5.0);
+        }
+        assert!(close(e.value(), 5.0, 1e-4));
+    }
+
+    #[test]
+    fn ewma_with_alpha_one_tracks_input_exactly() {
+        let mut e = Ewma::new(1.0);
+        for x in [3.0, -2.0, 7.5, 0.0] {
+            e.update(x);
+            assert_eq!(e.value(), x);
+        }
+        // 1 を超える alpha は 1 に丸める
+        let mut e = Ewma::new(4.0);
+        e.update(1.0);
+        e.update(9.0);
+        assert_eq!(e.value(), 9.0);
+    }
+
+    #[test]
+    fn ewma_ignores_non_finite_input() {
+        let mut e = Ewma::new(0.5);
+        e.update(f32::NAN);
+        assert_eq!(e.value(), 0.0);
+        e.update(2.0);
+        e.update(f32::INFINITY);
+        e.update(f32::NAN);
+        assert_eq!(e.value(), 2.0);
+    }
+
+    #[test]
+    fn running_stats_update_slice_skips_non_finite_and_counts_accepted() {
+        let mut st = RunningStats::new();
+        let accepted =
+            st.update_slice(&[1.0, f32::NAN, 2.0, f32::INFINITY, 3.0, f32::NEG_INFINITY]);
+        assert_eq!(accepted, 3);
+        assert_eq!(st.n, 3);
+        assert!(close(st.mean, 2.0, 1e-6));
+        assert_eq!(st.update_slice(&[]), 0);
+    }
+
+    #[test]
+    fn running_stats_update_ignores_nan() {
+        let mut st = RunningStats::new();
+        for x in [1.0, f32::NAN, 3.0] {
+            st.update(x);
+        }
+        assert_eq!(st.n, 2);
+        assert_eq!(st.mean, 2.0);
+        assert!(st.variance().is_finite());
+    }
+
+    #[test]
+    fn running_stats_rms_of_sine_is_amplitude_over_sqrt2() {
+        let mut st = RunningStats::new();
+        // 1 周期 16 点 × 4 周期、振幅 2 → RMS = 2/√2
+        for k in 0..64 {
+            st.update(2.0 * libm::sinf(2.0 * core::f32::consts::PI * k as f32 / 16.0));
+        }
+        assert!(close(st.rms(), 2.0 / core::f32::consts::SQRT_2, 1e-4));
+        // 直流分があれば RMS² = 平均² + 分散（母分散）
+        let mut dc = RunningStats::new();
+        for x in [3.0, 5.0, 3.0, 5.0] {
+            dc.update(x);
+        }
+        assert!(close(dc.rms(), libm::sqrtf(17.0), 1e-5));
+        assert_eq!(RunningStats::new().rms(), 0.0);
+    }
+
+    #[test]
+    fn running_stats_cv_and_stderr_match_hand_computed_values() {
+        // [2, 4, 4, 4, 5, 5, 7, 9]: 平均 5、標本分散 32/7
+        let mut st = RunningStats::new();
+        st.update_slice(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        let sd = libm::sqrtf(32.0 / 7.0);
+        assert!(close(st.stddev(), sd, 1e-5));
+        assert!(close(st.cv(), sd / 5.0, 1e-6));
+        assert!(close(st.stderr(), sd / libm::sqrtf(8.0), 1e-6));
+        // 平均 0 なら cv は 0、空なら stderr は 0
+        let mut zero = RunningStats::new();
+        zero.update_slice(&[-1.0, 1.0]);
+        assert_eq!(zero.cv(), 0.0);
+        assert_eq!(RunningStats::new().stderr(), 0.0);
+    }
+
+    #[test]
+    fn rolling_stats_forgets_samples_older_than_window() {
+        let mut w = RollingStats::<4>::new();
+        for x in [100.0, 100.0, 1.0, 2.0] {
+            w.push(x);
+        }
+        assert_eq!(w.len(), 4);
+        assert!(close(w.mean(), 50.75, 1e-5));
+        // 窓を越えて入れると古い 100 は効かなくなる
+        for x in [3.0, 4.0] {
+            w.push(x);
+        }
+        assert_eq!(w.len(), 4);
+        assert!(close(w.mean(), 2.5, 1e-6));
+        assert_eq!((w.min(), w.max()), (1.0, 4.0));
+        assert!(close(w.median(), 2.5, 1e-6));
+    }
+
+    #[test]
+    fn rolling_stats_empty_and_non_finite() {
+        let mut w = RollingStats::<3>::new();
+        assert!(w.is_empty());
+        assert_eq!(w.mean(), 0.0);
+        assert_eq!(w.median(), 0.0);
+        w.push(f32::NAN);
+        w.push(f32::INFINITY);
+        assert!(w.is_empty());
+    }
+
+    #[test]
+    fn quantile_median_of_ramp_lands_near_midpoint() {
+        // 0..1000 をよく混ぜた順に入れる（素数 7 飛ばしで 1 周）
+        let mut q = Quantile::new(0.5);
+        for k in 0..1000u32 {
+            q.update(((k * 7) % 1000) as f32);
+        }
+        assert_eq!(q.count(), 1000);
+        assert!(close(q.estimate(), 499.5, 25.0), "median {}", q.estimate());
+
+        let mut p95 = Quantile::new(0.95);
+        for k in 0..1000u32 {
+            p95.update(((k * 7) % 1000) as f32);
+        }
+        assert!(close(p95.estimate(), 950.0, 25.0), "p95 {}", p95.estimate());
+    }
+
+    #[test]
+    fn quantile_with_few_samples_uses_nearest_rank() {
+        let mut q = Quantile::new(0.5);
+        assert_eq!(q.estimate(), 0.0);
+        for x in [9.0, 1.0, 5.0] {
+            q.update(x);
+        }
+        assert_eq!(q.estimate(), 5.0);
+        q.update(f32::NAN);
+        assert_eq!(q.count(), 3);
+    }
+
+    #[test]
+    fn histogram_counts_known_values_per_bucket() {
+        // [0, 100) mA を 4 等分（25 mA 幅）
+        let mut h = Histogram::<4>::new(0.0, 100.0);
+        for x in [
+            0.0,
+            10.0,
+            24.9,
+            25.0,
+            49.0,
+            60.0,
+            99.9,
+            100.0,
+            500.0,
+            -3.0,
+            f32::NAN,
+        ] {
+            h.record(x);
+        }
+        // 範囲外は両端へ、NaN は数えない
+        assert_eq!(
+            [h.bucket(0), h.bucket(1), h.bucket(2), h.bucket(3)],
+            [4, 2, 1, 3]
+        );
+        assert_eq!(h.bucket(4), 0);
+        assert_eq!(h.total(), 10);
+        h.clear();
+        assert_eq!(h.total(), 0);
+    }
+
+    #[test]
+    fn trapezoidal_integration_is_exact_on_linear_ramp() {
+        // P = t [mW]（0..=1000 ms、100 ms ごと）。解析解は ∫ t dt = 1000² / 2 = 500_000 µW·s
+        let (mut rect, mut trap) = (Accumulators::new(0), Accumulators::new(0));
+        rect.update(0.0, 0.0, 0.0, 0);
+        trap.update_trapezoidal(0.0, 0.0, 0.0, 0);
+        for k in 1..=10 {
+            let p = (k * 100) as f32;
+            rect.update(0.0, 0.0, p, 100);
+            trap.update_trapezoidal(0.0, 0.0, p, 100);
+        }
+        assert_eq!(trap.snapshot().1, 500_000);
+        // 矩形近似は区間の終わりの値を使うので 1 区間分（100 ms × 平均の傾き）多い
+        assert_eq!(rect.snapshot().1, 550_000);
+    }
+
+    #[test]
+    fn accumulators_snapshot_then_reset_clears_counters() {
+        let mut acc = Accumulators::new(0);
+        acc.update(5.0, 100.0, 500.0, 1_000);
+        acc.update(5.0, 100.0, 500.0, 1_000);
+        let before = acc.snapshot();
+        assert_eq!(before, (200_000, 1_000_000, 2_000));
+        acc.reset();
+        assert_eq!(acc.snapshot(), (0, 0, 0));
+        assert_eq!(
+            (acc.active_ms(), acc.peak_power_mw(), acc.net_charge_uas()),
+            (0, 0.0, 0)
+        );
+        // 取っておいた値は残る
+        assert_eq!(before.1, 1_000_000);
+    }
+
+    #[test]
+    fn accumulators_split_charge_by_direction() {
+        let mut acc = Accumulators::new(0);
+        for _ in 0..2 {
+            acc.update(4.0, 100.0, 400.0, 1_000); // 放電 100 mA × 1 s
+            acc.update(4.0, -40.0, -160.0, 1_000); // 充電 40 mA × 1 s
+        }
+        // 放電 200_000 µA·s、充電 80_000 µA·s
+        assert_eq!(acc.snapshot().0, 200_000);
+        assert_eq!(acc.net_charge_uas(), 120_000);
+        assert!(close(
+            acc.readout_net_charge_mah(),
+            120_000.0 / 3_600_000.0,
+            1e-7
+        ));
+        assert!(close(
+            acc.readout_gross_charge_mah(),
+            280_000.0 / 3_600_000.0,
+            1e-7
+        ));
+        // 負の電力はエネルギーに入れない
+        assert_eq!(acc.snapshot().1, 800_000);
+    }
+
+    #[test]
+    fn soc_percent_clamps_at_both_ends_and_follows_capacity() {
+        let mut acc = Accumulators::new(0).with_capacity_uws(1_000_000);
+        assert_eq!(acc.soc_percent(), 100.0);
+        acc.update(0.0, 0.0, 250.0, 1_000); // 250_000 µW·s
+        assert!(close(acc.soc_percent(), 75.0, 1e-4));
+        // 途中で容量を倍にすると残量も変わる
+        acc.set_capacity_uws(2_000_000);
+        assert_eq!(acc.capacity_uws(), 2_000_000);
+        assert!(close(acc.soc_percent(), 87.5, 1e-4));
+        // 容量を超えて使っても 0 で止まる
+        acc.update(0.0, 0.0, 10_000.0, 1_000);
+        assert_eq!(acc.soc_percent(), 0.0);
+        acc.set_capacity_uws(0);
+        assert_eq!(acc.soc_percent(), 0.0);
+    }
+
+    #[test]
+    fn time_to_empty_divides_supplied_remaining_energy() {
+        let acc = Accumulators::new(0);
+        // 1 Wh 残りを 1 W で使うと 3600 s
+        assert_eq!(acc.time_to_empty_ms(1_000.0, 3_600_000_000), 3_600_000);
+        // 途中まで充電した電池: 設定した容量（単三 2.5 Wh）ではなく渡した残りで決まる
+        assert_eq!(acc.time_to_empty_ms(500.0, 900_000_000), 1_800_000);
+        assert_eq!(acc.time_to_empty_ms(1_000.0, 0), 0);
+        assert_eq!(acc.time_to_empty_ms(0.0, 3_600_000_000), u64::MAX);
+        assert_eq!(acc.time_to_empty_ms(f32::NAN, 3_600_000_000), u64::MAX);
+        assert_eq!(acc.time_to_empty_ms(1e-3, u128::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn time_to_empty_from_capacity_uses_remaining_energy() {
+        // 1 Wh の電池を 1000 mW で使うと 1 時間
+        let mut acc = Accumulators::new(0).with_capacity_uws(3_600_000_000);
+        assert_eq!(acc.time_to_empty_from_capacity_ms(1_000.0), 3_600_000);
+        // 半分使ったら 30 分
+        acc.update(0.0, 0.0, 1_000.0, 1_800_000);
+        assert_eq!(acc.remaining_uws(), 1_800_000_000);
+        assert_eq!(acc.time_to_empty_from_capacity_ms(1_000.0), 1_800_000);
+        assert_eq!(acc.time_to_empty_from_capacity_ms(0.0), u64::MAX);
+        assert_eq!(acc.time_to_empty_from_capacity_ms(f32::NAN), u64::MAX);
+    }
+
+    #[test]
+    fn peaks_are_retained_after_smaller_samples() {
+        let mut acc = Accumulators::new(0);
+        acc.update(5.0, 10.0, 50.0, 100);
+        acc.update(5.0, -300.0, -1_500.0, 100); // スパイク（向きによらず絶対値）
+        acc.update(5.0, 20.0, 100.0, 100);
+        acc.update(5.0, f32::NAN, f32::INFINITY, 100);
+        assert_eq!(acc.peak_current_ma(), 300.0);
+        assert_eq!(acc.peak_power_mw(), 1_500.0);
+    }
+
+    #[test]
+    fn energy_readout_joules_and_watt_hours_agree() {
+        // 1000 mW × 1 時間 = 3_600_000_000 µW·s = 3600 J = 1 Wh
+        let mut acc = Accumulators::new(0);
+        acc.update(0.0, 0.0, 1_000.0, 3_600_000);
+        assert_eq!(acc.snapshot().1, 3_600_000_000);
+        assert_eq!(acc.readout_energy_joules(), 3_600.0);
+        let (mwh, wh) = acc.readout_energy();
+        assert_eq!((mwh, wh), (1_000.0, 1.0));
+    }
+
+    #[test]
+    fn saturation_flag_trips_near_max() {
+        let mut acc = Accumulators::new(0);
+        acc.restore(0, u128::MAX - 50);
+        acc.update(0.0, 0.0, 1.0, 10); // +10 µW·s はまだ入る
+        assert!(!acc.saturated());
+        acc.update(0.0, 0.0, 1.0, 100); // +100 µW·s で頭打ち
+        assert!(acc.saturated());
+        assert_eq!(acc.snapshot().1, u128::MAX);
+        acc.reset();
+        assert!(!acc.saturated());
+    }
+
+    #[test]
+    fn current_cutoff_hysteresis_latches_without_chattering() {
+        // 10 mA 以上で積算開始、5 mA 未満で停止
+        let mut acc = Accumulators::new(10).with_cutoff_release_ma(5);
+        let sweep = [0.0, 6.0, 9.9, 10.0, 8.0, 6.0, 5.0, 4.9, 6.0, 9.0, 12.0];
+        let states: [bool; 11] = core::array::from_fn(|k| {
+            acc.update(5.0, sweep[k], 0.0, 1);
+            acc.is_active()
+        });
+        assert_eq!(
+            states,
+            [false, false, false, true, true, true, true, false, false, false, true]
+        );
+        // 積算されたのはアクティブな区間（10 + 8 + 6 + 5 + 12 mA × 1 ms）だけ
+        assert_eq!(acc.snapshot().0, 41);
+        assert_eq!(acc.active_ms(), 5);
+        // release が cutoff を超える指定は cutoff に丸める
+        assert_eq!(
+            Accumulators::new(10)
+                .with_cutoff_release_ma(20)
+                .cutoff_release_ma,
+            10
+        );
+    }
+
+    #[test]
+    fn held_ms_freezes_integration_during_outage() {
+        // 最後のサンプルは t=0 で、2 周期（1000 ms）まで有効。その後は読めないまま周回する
+        let valid_until = 1_000;
+        let dts: [u64; 4] = core::array::from_fn(|k| {
+            let from = k as u64 * 500;
+            held_ms(from, from + 500, valid_until)
+        });
+        assert_eq!(dts, [500, 500, 0, 0]);
+        // 切断中（有効期限 0）は何も積算しない
+        assert_eq!(held_ms(2_000, 2_500, 0), 0);
+        // 再接続後の最初のサンプルより前の区間は使わない
+        assert_eq!(held_ms_from(2_500, 3_000, 2_800, 4_000), 200);
+    }
+
+    #[test]
+    fn read_gap_does_not_over_count_energy() {
+        // 1000 mW を 500 ms ごとに読むが、t=1000..5000 は読めない（有効期限は最後のサンプル + 1000 ms）
+        let reads = [0, 500, 1_000, 5_000, 5_500, 6_000];
+        let mut acc = Accumulators::new(0);
+        let mut valid_until = 0;
+        let mut prev = 0;
+        for t in (0..=6_000).step_by(500) {
+            let dt = held_ms(prev, t, valid_until);
+            acc.update(5.0, 200.0, 1_000.0, dt as u32);
+            if reads.contains(&t) {
+                valid_until = t + 1_000;
+            }
+            prev = t;
+        }
+        // 積算されたのは 0..2000 と 5000..6000 の 3000 ms だけ（途切れた 3000 ms を水増ししない）
+        assert_eq!(acc.snapshot().1, 3_000_000);
+        assert_eq!(acc.uptime_ms, 3_000);
+    }
+
+    #[test]
+    fn battery_type_table_and_lookup() {
+        assert_eq!(BatteryType::AlkalineAa.capacity_wh(), 3.9);
+        assert_eq!(BatteryType::NimhAa.capacity_wh(), 2.5);
+        assert_eq!(BatteryType::AlkalineAaa.capacity_wh(), 1.8);
+        assert_eq!(BatteryType::NimhAaa.capacity_wh(), 1.0);
+        assert_eq!(BatteryType::Cr2032.capacity_wh(), 0.65);
+        assert_eq!(BatteryType::Cr2032.label(), "CR2032");
+        assert!(close(
+            battery_equiv_for(7.8, BatteryType::AlkalineAa),
+            2.0,
+            1e-6
+        ));
+        assert!(close(
+            battery_equiv_for(0.65, BatteryType::Cr2032),
+            1.0,
+            1e-6
+        ));
+    }
+
+    #[test]
+    fn battery_equiv_zero_capacity_is_zero() {
+        assert_eq!(battery_equiv(1.0, 0.0, 0.0), (0.0, 0.0));
+        assert_eq!(battery_equiv(1.0, -1.0, 2.0), (0.0, 0.5));
+    }
+
+    #[test]
+    fn summary_avg_power_is_energy_over_time() {
+        let mut acc = Accumulators::new(0);
+        // 200 mW を 3 s、500 mW を 1 s → 1100 mW·s / 4 s = 275 mW
+        acc.update(5.0, 40.0, 200.0, 3_000);
+        acc.update(5.0, 100.0, 500.0, 1_000);
+        let s = acc.summary();
+        assert_eq!(s.uptime_ms, 4_000);
+        assert!(close(s.avg_power_mw, 275.0, 1e-3));
+        assert!(close(s.energy_mwh, 1_100.0 / 3_600.0, 1e-6));
+        assert_eq!(Accumulators::new(0).summary().avg_power_mw, 0.0);
+    }
+
+    #[test]
+    fn minute_aggregator_splits_interval_at_boundary() {
+        let mut agg = MinuteAggregator::new();
+        assert_eq!(agg.advance(50_000, 1_000, 100_000, 100_000), None);
+        // 50 s..70 s は境界 60 s で分割: 前の窓へ 10 s、次の窓へ 10 s
+        let r = agg.advance(70_000, 2_000, 200_000, 200_000).unwrap();
+        assert_eq!(r.index, 1);
+        assert_eq!(r.avg_v_mv, 1_166); // (1000·50 + 2000·10) / 60
+        assert_eq!(r.avg_i_ua, 116_666);
+        assert_eq!(r.energy_uwms, 7_000_000_000);
+        assert_eq!(r.covered_ms, 60_000);
+        assert!(!r.saturated);
+        assert_eq!(agg.advance(70_000, 2_000, 200_000, 200_000), None);
+        let r = agg.advance(120_000, 3_000, 300_000, 300_000).unwrap();
+        assert_eq!(r.index, 2);
+        assert_eq!(r.avg_v_mv, 2_833); // (2000·10 + 3000·50) / 60
+        assert_eq!(r.energy_uwms, 17_000_000_000);
+    }
+
+    #[test]
+    fn minute_aggregator_reports_each_window_when_skipping_several() {
+        let mut agg = MinuteAggregator::new();
+        let mut reports = 0;
+        while let Some(r) = agg.advance(180_000, 5_000, 0, 0) {
+            reports += 1;
+            assert_eq!(
+                (r.index, r.avg_v_mv, r.covered_ms),
+                (reports, 5_000, 60_000)
+            );
+        }
+        assert_eq!(reports, 3);
+    }
+
+    #[test]
+    fn ten_second_window_over_25_s_run() {
+        let mut agg = MinuteAggregator::new().with_window_ms(10_000);
+        assert_eq!(agg.window_ms(), 10_000);
+        let mut full = [None; 2];
+        let mut n = 0;
+        while let Some(r) = agg.advance(25_000, 4_000, 1_000, 4_000) {
+            full[n] = Some(r);
+            n += 1;
+        }
+        assert_eq!(n, 2);
+        for (k, r) in full.iter().enumerate() {
+            let r = r.unwrap();
+            assert_eq!(
+                (r.index, r.avg_v_mv, r.energy_uwms, r.covered_ms),
+                (k as u64 + 1, 4_000, 40_000_000, 10_000)
+            );
+        }
+        // 20..25 s の端数は 3 窓目に持ち越される
+        let r = agg.advance(30_000, 2_000, 1_000, 2_000).unwrap();
+        assert_eq!(r.index, 3);
+        assert_eq!(r.avg_v_mv, 3_000);
+        assert_eq!(r.energy_uwms, 30_000_000);
+        assert_eq!(MinuteAggregator::new().with_window_ms(0).window_ms(), 1);
+    }
+
+    #[test]
+    fn clamp_dt_ms_caps_absurd_interval() {
+        assert_eq!(clamp_dt_ms(500, 5_000), (500, false));
+        assert_eq!(clamp_dt_ms(5_000, 5_000), (5_000, false));
+        assert_eq!(clamp_dt_ms(u64::MAX, 5_000), (5_000, true));
+        // 切り詰めた dt で積算すれば 1 周分を超えて入らない
+        let mut acc = Accumulators::new(0);
+        let (dt, clamped) = clamp_dt_ms(3_600_000_000, 5_000);
+        acc.update(5.0, 200.0, 1_000.0, dt as u32);
+        assert!(clamped);
+        assert_eq!(acc.snapshot().1, 5_000_000);
+    }
+
+    #[test]
+    fn derate_capacity_follows_curve() {
+        assert!(close(derate_capacity(2.5, 25.0), 2.5, 1e-6));
+        assert!(close(derate_capacity(2.5, 20.0), 2.5, 1e-6));
+        assert!(close(derate_capacity(2.5, 10.0), 2.125, 1e-5)); // 85%
+        assert!(close(derate_capacity(2.5, 0.0), 1.75, 1e-5)); // 70%
+        assert!(close(derate_capacity(2.5, -10.0), 1.375, 1e-5)); // 55%
+        assert!(close(derate_capacity(2.5, -40.0), 1.0, 1e-5)); // 40%
+        assert!(close(derate_capacity(2.5, f32::NAN), 2.5, 1e-6));
+    }
+
+    #[test]
+    fn one_watt_burns_16_67_mwh_per_minute() {
+        assert!(close(mwh_per_min(1_000.0), 16.666_667, 1e-4));
+        assert_eq!(mwh_per_min(0.0), 0.0);
+        // 一定入力なら平滑化後も同じ値に収束する
+        let mut rate = EnergyRate::new(0.5);
+        for _ in 0..32 {
+            rate.update(1_000.0);
+        }
+        assert!(close(rate.value(), 16.666_667, 1e-3));
+    }
+
+    #[test]
+    fn outlier_filter_drops_spike_in_ramp() {
+        let mut f: OutlierFilter<8> = OutlierFilter::new(5.0, 1.0);
+        let mut rejected = [false; 20];
+        for (k, r) in rejected.iter_mut().enumerate() {
+            // 100 mA から 5 mA ずつ増えるランプの 12 番目に 30 A のスパイク
+            let x = if k == 12 {
+                30_000.0
+            } else {
+                100.0 + 5.0 * k as f32
+            };
+            *r = f.reject(x);
+        }
+        let expected: [bool; 20] = core::array::from_fn(|k| k == 12);
+        assert_eq!(rejected, expected);
+        // 無効（factor 0）なら何も弾かない
+        let mut off: OutlierFilter<8> = OutlierFilter::new(0.0, 1.0);
+        assert!((0..8).all(|_| !off.reject(100.0)) && !off.reject(30_000.0));
+    }
+
+    #[test]
+    fn efficiency_percent_clamps_and_guards_zero_input() {
+        assert_eq!(efficiency_percent(1_000, 850), 85.0);
+        assert_eq!(efficiency_percent(0, 850), 0.0);
+        assert_eq!(efficiency_percent(-1_000, 850), 0.0);
+        assert_eq!(efficiency_percent(1_000, 1_200), 100.0);
+        assert_eq!(efficiency_percent(1_000, -50), 0.0);
+    }
+
+    #[test]
+    fn readout_in_converts_to_each_unit() {
+        // 1 W・100 mA を 1 時間
+        let mut acc = Accumulators::new(0);
+        acc.update(10.0, 100.0, 1_000.0, 3_600_000);
+        assert!(close(
+            acc.readout_in(DisplayUnit::MilliWattHour),
+            1_000.0,
+            1e-3
+        ));
+        assert!(close(acc.readout_in(DisplayUnit::WattHour), 1.0, 1e-6));
+        assert!(close(acc.readout_in(DisplayUnit::Joule), 3_600.0, 1e-3));
+        // mAh はエネルギーではなく電荷から
+        assert!(close(
+            acc.readout_in(DisplayUnit::MilliAmpHour),
+            100.0,
+            1e-4
+        ));
+        assert_eq!(DisplayUnit::Joule.label(), "J");
+    }
+
+    #[test]
+    fn update_at_stamps_only_new_extremes() {
+        let mut st = RunningStats::new();
+        st.update_at(5.0, 100);
+        assert_eq!((st.min_at_ms(), st.max_at_ms()), (100, 100));
+        st.update_at(4.0, 200);
+        assert_eq!((st.min_at_ms(), st.max_at_ms()), (200, 100));
+        // 同値や範囲内の値では時刻を動かさない
+        st.update_at(4.0, 300);
+        st.update_at(4.5, 400);
+        st.update_at(5.0, 500);
+        assert_eq!((st.min_at_ms(), st.max_at_ms()), (200, 100));
+        st.update_at(6.0, 600);
+        st.update_at(f32::NAN, 700);
+        assert_eq!((st.min_at_ms(), st.max_at_ms()), (200, 600));
+        assert_eq!((st.min, st.max, st.n), (4.0, 6.0, 6));
+    }
+
+    #[test]
+    fn group_average_resets_every_k_samples() {
+        // main.rs の PRINT_EVERY と同じ流れ: K 個たまったら平均を出して reset
+        const K: u64 = 4;
+        let samples = [1.0, 2.0, 3.0, 4.0, 10.0, 10.0, 20.0, 20.0, 7.0];
+        let mut group = RunningStats::new();
+        let mut means = [0.0f32; 2];
+        let mut emitted = 0;
+        for x in samples {
+            group.update(x);
+            if group.n >= K {
+                means[emitted] = group.mean;
+                emitted += 1;
+                group.reset();
+            }
+        }
+        assert_eq!(emitted, 2);
+        assert_eq!(means, [2.5, 15.0]);
+        // 端数の 1 サンプルは前の群を引きずらない
+        assert_eq!(
+            (group.n, group.mean, group.min, group.max),
+            (1, 7.0, 7.0, 7.0)
+        );
+    }
+
+    #[test]
+    fn load_ohms_5v_at_1a_is_5_ohm() {
+        assert_eq!(load_ohms(5_000, 1_000_000), 5.0);
+        assert_eq!(load_ohms(5_000, -1_000_000), 5.0);
+        assert_eq!(load_ohms(3_300, 1_000), 3_300.0);
+        assert_eq!(load_ohms(5_000, 0), f32::INFINITY);
+    }
+
+    #[test]
+    fn adaptive_period_stretches_when_quiet_and_snaps_back() {
+        let mut ap = AdaptivePeriod::new(500, 4_000, 3);
+        let changes: [Option<u32>; 12] = core::array::from_fn(|_| ap.update(false));
+        let mut expected = [None; 12];
+        expected[2] = Some(1_000);
+        expected[5] = Some(2_000);
+        expected[8] = Some(4_000);
+        assert_eq!(changes, expected);
+        assert_eq!(ap.period_ms(), 4_000);
+        // 1 回の動きで基準周期へ、続けて動いても変化なし
+        assert_eq!(ap.update(true), Some(500));
+        assert_eq!(ap.update(true), None);
+        // 静かな回数の途中で動きがあれば数え直し
+        ap.update(false);
+        ap.update(false);
+        ap.update(true);
+        assert_eq!(
+            (ap.update(false), ap.update(false), ap.update(false)),
+            (None, None, Some(1_000))
+        );
+    }
+
+    #[test]
+    fn sustained_fires_on_run_of_negative_shunt_voltage() {
+        // main.rs の逆接続検出と同じ: -100 µV 以下が 3 回続いたら発報
+        let mut reversed = Sustained::new(3);
+        let shunt_uv = [-500, -500, 20, -500, -500, -500, -500, 30];
+        let events: [Option<bool>; 8] =
+            core::array::from_fn(|k| reversed.update(shunt_uv[k] <= -100));
+        assert_eq!(
+            events,
+            [None, None, None, None, None, Some(true), None, Some(false)]
+        );
+        assert!(!reversed.is_active());
+        // need 0 は 1 扱い
+        assert_eq!(Sustained::new(0).update(true), Some(true));
+    }
+
+    #[test]
+    fn duty_cycle_counts_only_time_above_cutoff() {
+        // カットオフ 10 mA、ヒステリシスなし。50 mA を 300 ms、2 mA を 700 ms の繰り返し
+        let mut acc = Accumulators::new(10);
+        for _ in 0..5 {
+            acc.update(5.0, 50.0, 250.0, 300);
+            acc.update(5.0, 2.0, 10.0, 700);
+        }
+        assert_eq!(acc.uptime_ms, 5_000);
+        assert!(close(acc.duty_cycle(), 0.3, 1e-6));
+        assert!(close(acc.summary().duty_cycle, 0.3, 1e-6));
+        assert_eq!(Accumulators::new(10).duty_cycle(), 0.0);
+    }
+
+    #[test]
+    fn max_demand_smooths_short_burst_but_tracks_sustained_load() {
+        // 窓 4 サンプル、待機 100 mW
+        let mut short: MaxDemand<4> = MaxDemand::new();
+        for p in [
+            100.0, 100.0, 100.0, 100.0, 900.0, 100.0, 100.0, 100.0, 100.0,
+        ] {
+            short.update(p);
+        }
+        // 1 サンプルだけの 900 mW は窓平均で (900 + 3·100) / 4 = 300 mW
+        assert!(close(short.peak_demand_mw(), 300.0, 1e-4));
+
+        let mut long: MaxDemand<4> = MaxDemand::new();
+        for p in [100.0, 100.0, 900.0, 900.0, 900.0, 900.0, 900.0, 100.0] {
+            long.update(p);
+        }
+        // 窓より長く続けば需要はその水準になる
+        assert!(close(long.peak_demand_mw(), 900.0, 1e-4));
+        long.reset();
+        long.update(900.0);
+        assert_eq!(long.peak_demand_mw(), 0.0);
+    }
+
+    #[test]
+    fn coulomb_soc_seeded_2000_mah_after_500_mah() {
+        let mut acc = Accumulators::new(0).with_initial_charge_mah(2_000);
+        acc.update(3.7, 500.0, 1_850.0, 3_600_000); // 500 mA を 1 時間
+        assert!(close(acc.remaining_mah(), 1_500.0, 1e-3));
+        assert!(close(acc.soc_coulomb_percent(), 75.0, 1e-4));
+        // 充電した分は戻る
+        acc.update(3.7, -250.0, -925.0, 3_600_000);
+        assert!(close(acc.remaining_mah(), 1_750.0, 1e-3));
+        // 使い切れば残りは負、% は 0 にクランプ。初期電荷 0 なら 0
+        acc.set_initial_charge_mah(100);
+        assert!(acc.remaining_mah() < 0.0);
+        assert_eq!(acc.soc_coulomb_percent(), 0.0);
+        assert_eq!(Accumulators::new(0).soc_coulomb_percent(), 0.0);
+    }
+
+    #[test]
+    fn window_min_forgets_early_dip() {
+        let mut w: RollingStats<4> = RollingStats::new();
+        assert_eq!(w.window_min(), None);
+        let mut lifetime = RunningStats::new();
+        let mut mins = [0.0f32; 7];
+        // 起動直後に 3.1 V の谷、その後は 3.6〜3.7 V
+        for (k, v) in [
+            3_600.0, 3_100.0, 3_650.0, 3_700.0, 3_620.0, 3_680.0, 3_640.0,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            w.push(v);
+            lifetime.update(v);
+            mins[k] = w.window_min().unwrap();
+        }
+        assert_eq!(
+            mins,
+            [3_600.0, 3_100.0, 3_100.0, 3_100.0, 3_100.0, 3_620.0, 3_620.0]
+        );
+        // 起動後の最小は最初の谷に張り付いたまま
+        assert_eq!(lifetime.min, 3_100.0);
+    }
+
+    #[test]
+    fn ripple_percent_of_square_wave_current() {
+        // 100 mA ± 10 mA の矩形: 標本標準偏差 √(400/3) ≈ 11.547 → 11.547 %
+        let mut st = RunningStats::new();
+        st.update_slice(&[90.0, 110.0, 90.0, 110.0]);
+        assert!(close(st.ripple_percent(), 11.547, 1e-3));
+        // 向きが逆でも |mean| で割る
+        let mut neg = RunningStats::new();
+        neg.update_slice(&[-90.0, -110.0, -90.0, -110.0]);
+        assert!(close(neg.ripple_percent(), 11.547, 1e-3));
+        // 一定なら 0、1 サンプルや平均 0 も 0
+        let mut flat = RunningStats::new();
+        flat.update_slice(&[50.0; 8]);
+        assert_eq!(flat.ripple_percent(), 0.0);
+        let mut one = RunningStats::new();
+        one.update(50.0);
+        assert_eq!(one.ripple_percent(), 0.0);
+        let mut zero = RunningStats::new();
+        zero.update_slice(&[-10.0, 10.0]);
+        assert_eq!(zero.ripple_percent(), 0.0);
+    }
+
+    #[test]
+    fn first_sample_does_not_cover_startup_delay() {
+        // 起動 t=0、起動待ちのあと t=2000 に最初のサンプル（1000 mW）。以降 500 ms ごと
+        let first_ms = 2_000;
+        let mut acc = Accumulators::new(0);
+        let mut prev = 0;
+        for t in [2_500, 3_000] {
+            let dt = held_ms_from(prev, t, first_ms, u64::MAX);
+            acc.update(5.0, 200.0, 1_000.0, dt as u32);
+            prev = t;
+        }
+        // 積算は最初のサンプル以降の 1000 ms だけ（起動待ちの 2000 ms を数えない）
+        assert_eq!(acc.uptime_ms, 1_000);
+        assert_eq!(acc.snapshot().1, 1_000_000);
+        assert_eq!(held_ms(0, 2_500, u64::MAX), 2_500); // 起点をずらさなければ水増しされていた
+
+        // 集計窓も同じ起点から
+        let mut agg = MinuteAggregator::new().with_window_ms(10_000);
+        agg.set_valid_from(first_ms);
+        let r = agg.advance(10_000, 5_000, 200_000, 1_000_000).unwrap();
+        assert_eq!(
+            (r.covered_ms, r.avg_v_mv, r.energy_uwms),
+            (8_000, 5_000, 8_000_000_000)
+        );
+    }
+
+    #[test]
+    fn one_mwh_is_3_6_joules() {
+        // 1000 mW × 3.6 s = 1 mWh = 3.6 J
+        let mut acc = Accumulators::new(0);
+        acc.update(5.0, 200.0, 1_000.0, 3_600);
+        assert!(close(acc.readout_energy().0, 1.0, 1e-6));
+        assert!((acc.readout_energy_joules() - 3.6).abs() < 1e-12);
+        // main.rs の J 欄と同じ整形（J×100、整数部 7 桁）
+        let mut buf = [0u8; 16];
+        let j_x100 = libm::round(acc.readout_energy_joules() * 100.0) as u32;
+        let s = crate::fmt::fmt_fixed_sep(j_x100, 7, 2, b'.', &mut buf);
+        assert_eq!(s, "0000003.60");
+    }
+
+    #[test]
+    fn window_and_lifetime_percent_share_capacity_basis() {
+        // CR2032（0.65 Wh）に 1000 mW を 1 分
+        let capacity_uws = (BatteryType::Cr2032.capacity_wh() as f64 * 3.6e9) as u128;
+        let mut acc = Accumulators::new(0).with_capacity_uws(capacity_uws);
+        acc.update(3.0, 333.0, 1_000.0, 60_000);
+        let mut agg = MinuteAggregator::new();
+        let report = agg.advance(60_000, 3_000, 333_000, 1_000_000).unwrap();
+        // main.rs と同じく µW·ms → µW·s にして同じ容量・同じ式で
+        let window_pct = percent_of_capacity(report.energy_uwms as u128 / 1000, acc.capacity_uws());
+        assert_eq!(window_pct, acc.consumed_percent());
+        assert!(close(window_pct, 100.0 * 60.0 / (0.65 * 3_600.0), 1e-3));
+        assert_eq!(percent_of_capacity(1, 0), 0.0);
+    }
+
+    #[test]
+    fn idle_trigger_fires_once_after_sustained_idle() {
+        // 30 s 待機で発火。1 s ごとのサンプル
+        let mut trig = IdleTrigger::new(30_000);
+        // 起動直後の無負荷では発火しない
+        assert!((0..60).all(|s| !trig.update(false, s * 1_000)));
+        // 負荷 10 s → 待機。待機開始から 30 s の周で 1 回だけ
+        for s in 60..70 {
+            assert!(!trig.update(true, s * 1_000));
+        }
+        let fired: [bool; 60] =
+            core::array::from_fn(|k| trig.update(false, (70 + k as u64) * 1_000));
+        assert_eq!(fired.iter().filter(|&&f| f).count(), 1);
+        assert!(fired[30]);
+        assert_eq!(trig.idle_ms(129_000), 59_000);
+        // 途中で負荷が戻れば数え直し
+        trig.update(true, 130_000);
+        assert!(!trig.update(false, 131_000));
+        assert!(!trig.update(true, 150_000));
+        assert!(!trig.update(false, 151_000));
+        assert!(trig.update(false, 181_000));
+    }
+
+    #[test]
+    fn sample_counters_yield_percent() {
+        let mut c = SampleCounters::new();
+        assert_eq!(c.yield_percent(), 0.0);
+        for _ in 0..6 {
+            c.record_measurement();
+        }
+        c.record_none();
+        c.record_none();
+        c.record_none();
+        c.record_error();
+        assert_eq!(
+            c,
+            SampleCounters {
+                attempts: 10,
+                measurements: 6,
+                none: 3,
+                errors: 1
+            }
+        );
+        assert!(close(c.yield_percent(), 60.0, 1e-4));
+        c.reset();
+        assert_eq!(c.attempts, 0);
+    }
+
+    #[test]
+    fn max_window_before_i64_saturation() {
+        const YEAR_MS: u64 = 365 * 24 * 3_600 * 1_000;
+        // 2 W なら約 146 年
+        let at_2w = MinuteAggregator::max_window_ms(2_000_000);
+        assert_eq!(at_2w, i64::MAX as u64 / 2_000_000);
+        assert_eq!(at_2w / YEAR_MS, 146);
+        // 入力の上限（i32::MAX µW）でも u32::MAX ms（約 49 日）より長い。符号は問わない
+        assert!(MinuteAggregator::max_window_ms(i32::MAX as i64) > u32::MAX as u64);
+        assert_eq!(MinuteAggregator::max_window_ms(-2_000_000), at_2w);
+        assert_eq!(MinuteAggregator::max_window_ms(0), u64::MAX);
+
+        // 境界のちょうど手前までは飽和しない
+        let mut agg = MinuteAggregator::new().with_window_ms(u64::MAX);
+        assert_eq!(agg.advance(at_2w, 0, 0, 2_000_000), None);
+        agg.set_valid_until(at_2w); // 以降は時間だけ進める
+        let r = agg.advance(u64::MAX, 0, 0, 2_000_000).unwrap();
+        assert!(!r.saturated);
+        assert_eq!(r.energy_uwms, at_2w as i64 * 2_000_000);
+        // 1 ms でも超えれば頭打ちにしてフラグを立てる
+        let mut agg = MinuteAggregator::new().with_window_ms(u64::MAX);
+        agg.advance(at_2w + 1, 0, 0, 2_000_000);
+        agg.set_valid_until(at_2w + 1);
+        let r = agg.advance(u64::MAX, 0, 0, 2_000_000).unwrap();
+        assert!(r.saturated);
+        assert_eq!(r.energy_uwms, i64::MAX);
+    }
+
+    #[test]
+    fn power_cutoff_zeroes_sub_threshold_energy_only() {
+        let mut acc = Accumulators::new(0).with_power_cutoff_mw(5);
+        // 待機中のノイズ 3 mW（電流 1 mA）を 10 s
+        acc.update(3.0, 1.0, 3.0, 10_000);
+        acc.update_trapezoidal(3.0, 1.0, -4.0, 10_000);
+        assert_eq!(acc.snapshot().1, 0);
+        // 電流のカットオフとは独立なので、電荷と稼働時間は積算される
+        assert_eq!(acc.snapshot().0, 20_000);
+        assert_eq!(acc.uptime_ms, 20_000);
+        // しきい値以上はそのまま
+        acc.update(5.0, 2.0, 10.0, 1_000);
+        assert_eq!(acc.snapshot().1, 10_000);
+        // 0 なら無効（これまでどおり微小電力も積算）
+        let mut off = Accumulators::new(0);
+        off.update(3.0, 1.0, 3.0, 10_000);
+        assert_eq!(off.snapshot().1, 30_000);
+    }
 }
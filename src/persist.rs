@@ -0,0 +1,115 @@
+//! 累計値のフラッシュ保存（電源断・リセットをまたいで積算を続けるため）
+//! - 保存先はフラッシュ末尾の 1 セクタ（4 KiB、memory.x で FLASH から除外済み）
+//! - レコードの形式と追記ログの走査は lib 側（`record`）。ここはフラッシュの読み出し/消去/書き込みだけ
+//! - 空きスロットへ追記し、セクタが満杯のときだけ消去する。前回と同じ値なら書かない
+
+use pico_va_monitor::record::{self, RECORD_LEN, SECTOR_SIZE, SLOTS};
+use rp2040_hal::rom_data;
+
+pub use pico_va_monitor::record::Totals;
+
+/// 保存セクタのフラッシュ先頭からのオフセット（2 MiB フラッシュの最終セクタ）
+pub const FLASH_OFFSET: u32 = 2048 * 1024 - SECTOR_SIZE as u32;
+
+const PAGE_SIZE: usize = 256;
+const XIP_BASE: u32 = 0x1000_0000;
+
+/// 保存セクタへの追記先
+pub struct Store {
+    /// 次に書くスロット（`SLOTS` なら満杯）
+    next_slot: usize,
+    /// 最後に書いた（または起動時に読んだ）値
+    last: Option<Totals>,
+}
+
+impl Store {
+    /// 保存セクタを走査して開く。最後の有効なレコードがあれば一緒に返す（空/破損なら `None`）
+    pub fn open() -> (Self, Option<Totals>) {
+        // 安全：FLASH_OFFSET はフラッシュ内で、memory.x によりプログラム領域と重ならない
+        // XIP 経由の読み出しなので通常のメモリ読み出しでよい
+        let sector = unsafe {
+            core::slice::from_raw_parts((XIP_BASE + FLASH_OFFSET) as *const u8, SECTOR_SIZE)
+        };
+        let scan = record::scan(sector);
+        (
+            Self {
+                next_slot: scan.next_slot,
+                last: scan.latest,
+            },
+            scan.latest,
+        )
+    }
+
+    /// 次の空きスロットへ追記する（満杯ならセクタを消去して先頭へ）。前回と同じ値なら何もしない
+    /// 追記は 1 ページの書き込みで数 ms、消去を伴う回（`SLOTS` 回に 1 回）は割り込み禁止で数十〜数百 ms かかる
+    pub fn store(&mut self, totals: &Totals) {
+        if self.last == Some(*totals) {
+            return;
+        }
+        let erase = self.next_slot >= SLOTS;
+        let slot = if erase { 0 } else { self.next_slot };
+        let offset = slot * RECORD_LEN;
+        let page_start = offset - offset % PAGE_SIZE;
+        // 書き込みは 1 と 0 の AND にしかならないので、ほかのスロットは 0xFF のまま書いて元の値を残す
+        let mut page = [0xFFu8; PAGE_SIZE];
+        page[offset - page_start..][..RECORD_LEN].copy_from_slice(&record::encode(totals));
+        cortex_m::interrupt::free(|_| {
+            // 安全：XIP を止めている間はフラッシュ上のコードを実行できないため、
+            // ROM 関数のポインタを先に引いてから RAM 上の関数で消去/書き込みを行う
+            unsafe {
+                write_page_ram(
+                    FLASH_OFFSET,
+                    page_start as u32,
+                    &page,
+                    erase,
+                    RomFns::lookup(),
+                )
+            };
+        });
+        self.next_slot = slot + 1;
+        self.last = Some(*totals);
+    }
+}
+
+/// 消去/書き込みに使う ROM 関数（XIP 停止前に取得しておく）
+struct RomFns {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    flash_enter_cmd_xip: unsafe extern "C" fn(),
+}
+
+impl RomFns {
+    fn lookup() -> Self {
+        Self {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+            flash_enter_cmd_xip: rom_data::flash_enter_cmd_xip::ptr(),
+        }
+    }
+}
+
+/// RAM 上で実行する（必要ならセクタ消去 +）1 ページ書き込み（ブロック消去コマンド 0xD8、セクタ単位）
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_page_ram(
+    sector: u32,
+    page_offset: u32,
+    page: &[u8; PAGE_SIZE],
+    erase: bool,
+    f: RomFns,
+) {
+    (f.connect_internal_flash)();
+    (f.flash_exit_xip)();
+    if erase {
+        (f.flash_range_erase)(sector, SECTOR_SIZE, 1 << 16, 0xD8);
+    }
+    (f.flash_range_program)(sector + page_offset, page.as_ptr(), PAGE_SIZE);
+    (f.flash_flush_cache)();
+    (f.flash_enter_cmd_xip)();
+}
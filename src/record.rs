@@ -0,0 +1,192 @@
+//! 累計値の保存レコード（フラッシュの読み書きは `main.rs` 側の `persist`、ここは HAL に依存しない部分）
+//! - レコード: MAGIC u32 / charge_uas i64 / energy_uws i64 / uptime_ms u64 / CRC32 u32（LE、計 32 バイト）
+//! - 保存セクタ（4 KiB）を `SLOTS` 個のスロットに分け、先頭から順に追記する。満杯になったら消去して先頭へ戻る
+//!   （毎回セクタを消去すると 1 分ごとの保存で約 70 日で書き換え寿命に届くため、消去は `SLOTS` 回に 1 回にする）
+//! - 未書き込み（0xFF）や壊れたレコードは `decode` が `None` を返し、`scan` は最後の有効なレコードを採る
+
+/// レコード識別子（"VAM1"）
+pub const MAGIC: u32 = 0x5641_4D31;
+
+/// レコード長 [byte]
+pub const RECORD_LEN: usize = 32;
+
+/// 保存セクタの大きさ [byte]（消去の単位）
+pub const SECTOR_SIZE: usize = 4096;
+
+/// 1 セクタに入るレコード数
+pub const SLOTS: usize = SECTOR_SIZE / RECORD_LEN;
+
+/// 保存する累計値
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Totals {
+    /// 累計電荷 [µA·s]（充電方向は負）
+    pub charge_uas: i64,
+    /// 累計エネルギー [µW·s]
+    pub energy_uws: i64,
+    /// 累計稼働時間 [ms]
+    pub uptime_ms: u64,
+}
+
+/// `totals` をレコードに詰める
+pub fn encode(totals: &Totals) -> [u8; RECORD_LEN] {
+    let mut buf = [0u8; RECORD_LEN];
+    buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    buf[4..12].copy_from_slice(&totals.charge_uas.to_le_bytes());
+    buf[12..20].copy_from_slice(&totals.energy_uws.to_le_bytes());
+    buf[20..28].copy_from_slice(&totals.uptime_ms.to_le_bytes());
+    let crc = crc32(&buf[..28]);
+    buf[28..32].copy_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+/// レコードを復元。MAGIC か CRC が合わなければ `None`
+pub fn decode(buf: &[u8]) -> Option<Totals> {
+    if buf.len() < RECORD_LEN {
+        return None;
+    }
+    let word = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+    let dword = |i: usize| {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&buf[i..i + 8]);
+        b
+    };
+    if word(0) != MAGIC || word(28) != crc32(&buf[..28]) {
+        return None;
+    }
+    Some(Totals {
+        charge_uas: i64::from_le_bytes(dword(4)),
+        energy_uws: i64::from_le_bytes(dword(12)),
+        uptime_ms: u64::from_le_bytes(dword(20)),
+    })
+}
+
+/// CRC-32（IEEE 802.3、反転多項式 0xEDB88320）
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// 保存セクタを走査した結果
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Scan {
+    /// 最後の有効なレコード（なければ `None`）
+    pub latest: Option<Totals>,
+    /// 次に書くスロット（最後に使われたスロットの次。`SLOTS` なら満杯で、消去してから 0 に書く）
+    pub next_slot: usize,
+}
+
+/// 保存セクタ（`SLOTS` 個のスロット）を走査する
+/// 書きかけで電源が落ちたスロットは壊れたレコードとして読み飛ばすが、使用済みとして次の書き込み位置はその後ろにする
+pub fn scan(sector: &[u8]) -> Scan {
+    let mut latest = None;
+    let mut next_slot = 0;
+    for (i, slot) in sector
+        .as_chunks::<RECORD_LEN>()
+        .0
+        .iter()
+        .take(SLOTS)
+        .enumerate()
+    {
+        if slot.iter().all(|&b| b == 0xFF) {
+            continue;
+        }
+        next_slot = i + 1;
+        if let Some(t) = decode(slot) {
+            latest = Some(t);
+        }
+    }
+    Scan { latest, next_slot }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T: Totals = Totals {
+        charge_uas: -123_456_789,
+        energy_uws: 9_000_000_000,
+        uptime_ms: 86_400_000,
+    };
+
+    #[test]
+    fn record_round_trip() {
+        let rec = encode(&T);
+        assert_eq!(&rec[..4], &MAGIC.to_le_bytes());
+        assert_eq!(decode(&rec), Some(T));
+        assert_eq!(decode(&rec[..RECORD_LEN - 1]), None);
+    }
+
+    #[test]
+    fn crc32_matches_ieee_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn decode_rejects_blank_and_corrupt_records() {
+        assert_eq!(decode(&[0xFF; RECORD_LEN]), None);
+        let mut rec = encode(&T);
+        rec[10] ^= 0x01;
+        assert_eq!(decode(&rec), None);
+        let mut rec = encode(&T);
+        rec[0] = 0;
+        assert_eq!(decode(&rec), None);
+    }
+
+    fn sector_with(records: &[[u8; RECORD_LEN]]) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0xFF; SECTOR_SIZE];
+        for (i, r) in records.iter().enumerate() {
+            sector[i * RECORD_LEN..][..RECORD_LEN].copy_from_slice(r);
+        }
+        sector
+    }
+
+    #[test]
+    fn scan_blank_sector_starts_at_first_slot() {
+        assert_eq!(
+            scan(&[0xFF; SECTOR_SIZE]),
+            Scan {
+                latest: None,
+                next_slot: 0
+            }
+        );
+    }
+
+    #[test]
+    fn scan_takes_last_valid_record_and_skips_torn_write() {
+        let older = Totals { uptime_ms: 1, ..T };
+        let mut torn = encode(&Totals { uptime_ms: 3, ..T });
+        torn[20..].fill(0xFF); // 書きかけで電源断
+        let sector = sector_with(&[encode(&older), encode(&T), torn]);
+        assert_eq!(
+            scan(&sector),
+            Scan {
+                latest: Some(T),
+                next_slot: 3
+            }
+        );
+    }
+
+    #[test]
+    fn scan_full_sector_asks_for_erase() {
+        let records = [encode(&T); SLOTS];
+        let sector = sector_with(&records);
+        assert_eq!(
+            scan(&sector),
+            Scan {
+                latest: Some(T),
+                next_slot: SLOTS
+            }
+        );
+        assert_eq!(SLOTS, 128);
+    }
+}
@@ -0,0 +1,270 @@
+//! 1サンプルの計測値と、その換算・判定（HAL に依存しない部分）
+//! - `main.rs` の `ina_next` が INA219 のレジスタからここの `Measurement` を作る
+//! - 設定（シャント抵抗、反転、フルスケール）は引数で受け取る（ビルド時の定数は `main.rs` 側）
+
+/// 1サンプルの計測値（単位はフィールド名の接尾辞）
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Measurement {
+    /// バス電圧 [mV]
+    pub bus_mv: i32,
+    /// シャント電圧 [µV]（校正に依らない生の測定値、10µV 分解能）
+    pub shunt_uv: i32,
+    /// 電流 [µA]（未校正なら `None`）
+    pub current_ua: Option<i32>,
+    /// 電力 [µW]（未校正なら `None`）
+    pub power_uw: Option<i32>,
+    /// 演算オーバーフロー（電流/電力はシャント電圧から求め直した値）
+    pub overflow: bool,
+    /// シャント電圧レジスタの生値 [10µV/LSB]（INVERT_CURRENT でも反転しない）
+    pub shunt_reg: i16,
+    /// バス電圧レジスタの電圧部分の生値 [4mV/LSB]
+    pub bus_reg: u16,
+}
+
+impl Measurement {
+    /// 全て 0 の計測値（履歴バッファの初期値用）
+    pub const ZERO: Self = Self {
+        bus_mv: 0,
+        shunt_uv: 0,
+        current_ua: None,
+        power_uw: None,
+        overflow: false,
+        shunt_reg: 0,
+        bus_reg: 0,
+    };
+
+    /// 電圧の2レジスタの生値から作る（電流/電力は未校正扱いの `None`）
+    pub const fn from_regs(shunt_reg: i16, bus_reg: u16) -> Self {
+        Self {
+            bus_mv: bus_reg_to_mv(bus_reg),
+            shunt_uv: shunt_reg_to_uv(shunt_reg),
+            shunt_reg,
+            bus_reg,
+            ..Self::ZERO
+        }
+    }
+
+    /// INA219 が演算オーバーフロー（OVF）を立てたときの計測値。電流/電力レジスタは不正なので、
+    /// 電流はシャント電圧 / `shunt_uohm` から、電力はバス電圧 × 電流から求め直して `overflow` を立てる
+    pub fn from_overflow_regs(shunt_reg: i16, bus_reg: u16, shunt_uohm: u32) -> Self {
+        let regs = Self::from_regs(shunt_reg, bus_reg);
+        let current_ua = shunt_uv_to_ua(regs.shunt_uv, shunt_uohm);
+        Self {
+            current_ua: Some(current_ua),
+            power_uw: Some(power_uw(regs.bus_mv, current_ua)),
+            overflow: true,
+            ..regs
+        }
+    }
+
+    /// 電流の向きを反転した計測値（シャント電圧/電流/電力の符号を反転、バス電圧と生のレジスタ値はそのまま）
+    pub fn inverted(self) -> Self {
+        Self {
+            shunt_uv: self.shunt_uv.saturating_neg(),
+            current_ua: self.current_ua.map(i32::saturating_neg),
+            power_uw: self.power_uw.map(i32::saturating_neg),
+            ..self
+        }
+    }
+}
+
+/// シャント電圧レジスタの生値 [10µV/LSB, 2の補数] → µV（±320 mV レンジで ±32000）
+pub const fn shunt_reg_to_uv(shunt_reg: i16) -> i32 {
+    shunt_reg as i32 * 10
+}
+
+/// バス電圧レジスタの電圧部分（下位 3 ビットのフラグを除いた 13 ビット）[4mV/LSB] → mV
+pub const fn bus_reg_to_mv(bus_reg: u16) -> i32 {
+    bus_reg as i32 * 4
+}
+
+/// 符号の取り決めをそろえる（`invert` なら反転）。`ina_next` の直後に通す
+pub fn normalize_sign(m: Measurement, invert: bool) -> Measurement {
+    if invert {
+        m.inverted()
+    } else {
+        m
+    }
+}
+
+/// シャント電圧 [µV] → 電流 [µA]（I = V / shunt_uohm、i32 で飽和。抵抗 0 なら 0）
+pub fn shunt_uv_to_ua(shunt_uv: i32, shunt_uohm: u32) -> i32 {
+    if shunt_uohm == 0 {
+        return 0;
+    }
+    let i_ua = shunt_uv as i64 * 1_000_000 / shunt_uohm as i64;
+    i_ua.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// バス電圧 [mV] × 電流 [µA] → 電力 [µW]（i32 で飽和）
+pub fn power_uw(bus_mv: i32, current_ua: i32) -> i32 {
+    let p_uw = bus_mv as i64 * current_ua as i64 / 1000;
+    p_uw.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// バス電圧/シャント電圧がフルスケール（`bus_fsr_mv` / `shunt_fsr_uv`）の `warn_pct` % を超えていれば、
+/// どちら（"bus" / "shunt"）かを返す。両方なら "bus"
+/// シャントは絶対値で判定（逆向きの電流でも振り切れるため）
+pub fn near_full_scale(
+    bus_mv: i32,
+    shunt_uv: i32,
+    bus_fsr_mv: i32,
+    shunt_fsr_uv: i32,
+    warn_pct: i32,
+) -> Option<&'static str> {
+    if bus_mv as i64 * 100 > bus_fsr_mv as i64 * warn_pct as i64 {
+        Some("bus")
+    } else if shunt_uv.unsigned_abs() as i64 * 100 > shunt_fsr_uv as i64 * warn_pct as i64 {
+        Some("shunt")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(shunt_uv: i32, current_ua: Option<i32>, power_uw: Option<i32>) -> Measurement {
+        Measurement {
+            bus_mv: 5_000,
+            shunt_uv,
+            current_ua,
+            power_uw,
+            shunt_reg: 12,
+            bus_reg: 1250,
+            ..Measurement::ZERO
+        }
+    }
+
+    #[test]
+    fn normalize_sign_flips_shunt_current_and_power_only_when_inverted() {
+        let x = m(1_000, Some(10_000), Some(50_000));
+        assert_eq!(normalize_sign(x, false), x);
+        let y = normalize_sign(x, true);
+        assert_eq!(
+            (y.shunt_uv, y.current_ua, y.power_uw),
+            (-1_000, Some(-10_000), Some(-50_000))
+        );
+        assert_eq!((y.bus_mv, y.shunt_reg, y.bus_reg), (5_000, 12, 1250));
+    }
+
+    #[test]
+    fn normalize_sign_keeps_uncalibrated_none_and_saturates() {
+        let y = normalize_sign(m(i32::MIN, None, None), true);
+        assert_eq!(
+            (y.shunt_uv, y.current_ua, y.power_uw),
+            (i32::MAX, None, None)
+        );
+    }
+
+    #[test]
+    fn shunt_uv_to_ua_divides_by_resistance() {
+        // 0.1 Ω に 10 mV → 100 mA
+        assert_eq!(shunt_uv_to_ua(10_000, 100_000), 100_000);
+        assert_eq!(shunt_uv_to_ua(-10_000, 100_000), -100_000);
+        assert_eq!(shunt_uv_to_ua(10_000, 0), 0);
+        // 1 µΩ に ±320 mV は i32 に収まらないので飽和
+        assert_eq!(shunt_uv_to_ua(320_000, 1), i32::MAX);
+        assert_eq!(shunt_uv_to_ua(-320_000, 1), i32::MIN);
+    }
+
+    #[test]
+    fn near_full_scale_checks_bus_then_absolute_shunt() {
+        assert_eq!(near_full_scale(28_800, 0, 32_000, 320_000, 90), None);
+        assert_eq!(near_full_scale(28_801, 0, 32_000, 320_000, 90), Some("bus"));
+        assert_eq!(
+            near_full_scale(0, 288_001, 32_000, 320_000, 90),
+            Some("shunt")
+        );
+        assert_eq!(
+            near_full_scale(0, -288_001, 32_000, 320_000, 90),
+            Some("shunt")
+        );
+        assert_eq!(
+            near_full_scale(32_000, 320_000, 32_000, 320_000, 90),
+            Some("bus")
+        );
+    }
+
+    #[test]
+    fn register_decoding_matches_datasheet_scale() {
+        // シャント: ±320 mV レンジの端は ±32000（0x7D00 / 0x8300）、10 µV/LSB
+        assert_eq!(shunt_reg_to_uv(0x7D00), 320_000);
+        assert_eq!(shunt_reg_to_uv(0x8300u16 as i16), -320_000);
+        assert_eq!(shunt_reg_to_uv(-1), -10);
+        // バス: 生のレジスタ 0x5D9A（フラグ付き）の電圧部分は 0xBB3 = 2995 → 11.98 V
+        assert_eq!(bus_reg_to_mv(0x5D9A >> 3), 11_980);
+        let m = Measurement::from_regs(1_000, 1_250);
+        assert_eq!(
+            (m.shunt_uv, m.bus_mv, m.current_ua, m.power_uw),
+            (10_000, 5_000, None, None)
+        );
+        assert_eq!((m.shunt_reg, m.bus_reg, m.overflow), (1_000, 1_250, false));
+    }
+
+    #[test]
+    fn inversion_flips_discharge_sign_and_round_trips() {
+        // 逆向きに配線したリグの放電（負の電流/電力）が正になる
+        let wired_backwards = m(-2_500, Some(-25_000), Some(-125_000));
+        let y = normalize_sign(wired_backwards, true);
+        assert!(y.shunt_uv > 0 && y.current_ua > Some(0) && y.power_uw > Some(0));
+        assert_eq!(y.inverted(), wired_backwards);
+        assert_eq!(Measurement::ZERO.inverted(), Measurement::ZERO);
+    }
+
+    #[test]
+    fn near_full_scale_threshold_for_32v_320mv_ranges() {
+        // 32 V / ±320 mV レンジの 90% は 28.8 V / 288 mV（ちょうどは超えていない扱い）
+        let check = |bus_mv, shunt_uv| near_full_scale(bus_mv, shunt_uv, 32_000, 320_000, 90);
+        assert_eq!(check(12_000, 50_000), None);
+        assert_eq!(check(28_800, 288_000), None);
+        assert_eq!(check(31_000, 0), Some("bus"));
+        assert_eq!(check(12_000, 300_000), Some("shunt"));
+        assert_eq!(check(12_000, -300_000), Some("shunt"));
+        // 16 V / ±40 mV レンジでは同じ値でも判定が変わる
+        assert_eq!(near_full_scale(15_000, 0, 16_000, 40_000, 90), Some("bus"));
+        assert_eq!(
+            near_full_scale(5_000, 37_000, 16_000, 40_000, 90),
+            Some("shunt")
+        );
+    }
+
+    #[test]
+    fn overflow_path_recomputes_current_and_power_from_voltages() {
+        // 0.1 Ω、シャント 100 mV（10000 × 10 µV）、バス 12 V（3000 × 4 mV）→ 1 A、12 W
+        let m = Measurement::from_overflow_regs(10_000, 3_000, 100_000);
+        assert_eq!((m.shunt_uv, m.bus_mv), (100_000, 12_000));
+        assert_eq!(
+            (m.current_ua, m.power_uw, m.overflow),
+            (Some(1_000_000), Some(12_000_000), true)
+        );
+        assert_eq!((m.shunt_reg, m.bus_reg), (10_000, 3_000));
+        // 逆向き
+        let m = Measurement::from_overflow_regs(-10_000, 3_000, 100_000);
+        assert_eq!(
+            (m.current_ua, m.power_uw),
+            (Some(-1_000_000), Some(-12_000_000))
+        );
+    }
+
+    #[test]
+    fn overflow_path_saturates_extreme_bus_and_current() {
+        // 1 mΩ に ±320 mV → ±320 A、バス最大 32.76 V（8191 × 4 mV）→ 約 ±10.5 kW は i32 µW に収まらない
+        let m = Measurement::from_overflow_regs(32_000, 8_191, 1_000);
+        assert_eq!(m.current_ua, Some(320_000_000));
+        assert_eq!(m.power_uw, Some(i32::MAX));
+        let m = Measurement::from_overflow_regs(-32_000, 8_191, 1_000);
+        assert_eq!(m.power_uw, Some(i32::MIN));
+        // 電流も飽和する抵抗でも電力は飽和したまま
+        let m = Measurement::from_overflow_regs(i16::MIN, u16::MAX >> 3, 1);
+        assert_eq!((m.current_ua, m.power_uw), (Some(i32::MIN), Some(i32::MIN)));
+        assert_eq!(power_uw(i32::MAX, i32::MAX), i32::MAX);
+        assert_eq!(power_uw(5_000, 200_000), 1_000_000);
+        // 抵抗 0 なら電流/電力 0
+        assert_eq!(
+            Measurement::from_overflow_regs(100, 1_250, 0).power_uw,
+            Some(0)
+        );
+    }
+}
@@ -1,28 +1,116 @@
 //! ASCII バー/ミニメータ描画（no_std）
-//! - `pct(x, max)` で 0..=100[%] 正規化
-//! - `render_bar(percent, buf)` で `====>.....` 風バー文字列を生成
+//! - `pct(x, max)` で 0..=100[%] 正規化（`pct_log(x, min, max)` は対数スケール）
+//! - `AutoScale` で最大値を追従（減衰付き）して `pct()` の基準を自動決定
+//! - `render_bar(percent, buf)` で `====>.....` 風バー文字列を生成（幅はバッファ長 W）
+//! - `render_bar_marked(percent, threshold, buf)` で閾値マーカー `!` 付きのバーを生成
+//! - `render_bar_labeled(percent, buf)` でバー中央に `42%` を埋め込む
+//! - `render_bar_unicode(percent, buf)` でブロック文字（1/8 刻み）のバーを生成
+//! - `render_bar_signed(value, max, buf)` で中央原点の符号付きバーを生成
+//! - `Sparkline<N>` で直近 N サンプルのスパークラインを生成
 
 #![allow(dead_code)]
 
 use core::str;
 
+use crate::history::SampleHistory;
+
+/// 既定のバー幅（文字数）
 pub const BAR_W: usize = 32;
 
 /// 値 x を [0, max] に正規化して 0..=100[%] を返す（飽和）
 pub fn pct(x: f32, max: f32) -> u8 {
-    if !(x.is_finite()) || max <= 0.0 { return 0; }
+    if !(x.is_finite()) || max <= 0.0 {
+        return 0;
+    }
     let p = (x / max) * 100.0;
-    if p <= 0.0 { 0 } else if p >= 100.0 { 100 } else { p as u8 }
+    if p <= 0.0 {
+        0
+    } else if p >= 100.0 {
+        100
+    } else {
+        p as u8
+    }
+}
+
+/// 値 x を対数スケールで [min, max] → 0..=100[%] に正規化（飽和）
+/// µA〜A のように桁の違う範囲を1本のバーで見るため。x/min が 0 以下なら 0
+pub fn pct_log(x: f32, min: f32, max: f32) -> u8 {
+    if !(x.is_finite()) || x <= 0.0 || min <= 0.0 || max <= min {
+        return 0;
+    }
+    let lo = libm::log10f(min);
+    let p = (libm::log10f(x) - lo) / (libm::log10f(max) - lo) * 100.0;
+    if p <= 0.0 {
+        0
+    } else if p >= 100.0 {
+        100
+    } else {
+        p as u8
+    }
+}
+
+/// これまでの最大値を基準に `pct()` するオートスケール
+/// 減衰係数（0, 1] を毎回の入力で掛けて、初期の単発スパイクで目盛りが潰れ続けないようにする
+#[derive(Clone, Copy)]
+pub struct AutoScale {
+    peak: f32,
+    decay: f32,
+}
+
+impl AutoScale {
+    /// 減衰なし（最大値を保持し続ける）
+    pub const fn new() -> Self {
+        Self {
+            peak: 0.0,
+            decay: 1.0,
+        }
+    }
+
+    /// 減衰付き。入力ごとに peak *= decay（例: 0.99）。範囲外は 1.0（減衰なし）
+    pub fn with_decay(decay: f32) -> Self {
+        let decay = if decay > 0.0 && decay <= 1.0 {
+            decay
+        } else {
+            1.0
+        };
+        Self { peak: 0.0, decay }
+    }
+
+    /// 基準最大値を更新して、x の割合（0..=100[%]）を返す
+    pub fn pct(&mut self, x: f32) -> u8 {
+        self.peak *= self.decay;
+        if x.is_finite() && x > self.peak {
+            self.peak = x;
+        }
+        pct(x, self.peak)
+    }
+
+    /// 現在の基準最大値
+    pub fn max(&self) -> f32 {
+        self.peak
+    }
+}
+
+impl Default for AutoScale {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// 与えた%に応じて `=====>.....` 形式のバーを生成して `&str` を返す
-/// バッファは呼び出し側に `[u8; BAR_W]` を用意させる（no_std対応）
-pub fn render_bar(percent: u8, buf: &mut [u8; BAR_W]) -> &str {
-    let filled = ((percent as usize) * (BAR_W - 1)) / 100; // 最終1文字は余白/末尾
-    for i in 0..BAR_W { buf[i] = b'.'; }
+/// バッファは呼び出し側に `[u8; W]` を用意させる（no_std対応）。幅 W は呼び出し側で選ぶ
+pub fn render_bar<const W: usize>(percent: u8, buf: &mut [u8; W]) -> &str {
+    if W == 0 {
+        return "";
+    }
+    let percent = percent.min(100);
+    let filled = ((percent as usize) * (W - 1)) / 100; // 最終1文字は余白/末尾
+    buf.fill(b'.');
     if filled > 0 {
-        for i in 0..filled.min(BAR_W - 1) { buf[i] = b'='; }
-        if filled < BAR_W { buf[filled] = b'>'; }
+        buf[..filled.min(W - 1)].fill(b'=');
+        if filled < W {
+            buf[filled] = b'>';
+        }
     } else {
         buf[0] = b'>';
     }
@@ -30,6 +118,171 @@ pub fn render_bar(percent: u8, buf: &mut [u8; BAR_W]) -> &str {
     unsafe { str::from_utf8_unchecked(&buf[..]) }
 }
 
+/// `render_bar` に閾値マーカー `!` を重ねる（充填の有無に関わらずマーカーを優先）
+/// マーカー列は充填と同じ換算 `(threshold * (W-1)) / 100`
+pub fn render_bar_marked<const W: usize>(
+    percent: u8,
+    threshold_percent: u8,
+    buf: &mut [u8; W],
+) -> &str {
+    if W == 0 {
+        return "";
+    }
+    render_bar(percent, buf);
+    let col = ((threshold_percent.min(100) as usize) * (W - 1)) / 100;
+    buf[col] = b'!';
+    // 安全：ASCIIのみを書き込む
+    unsafe { str::from_utf8_unchecked(&buf[..]) }
+}
+
+/// `render_bar` の中央に右詰め 4 桁の `" 42%"` / `"100%"` を重ねる（全体幅は W のまま）
+/// 桁数が変わっても表示位置がずれないよう常に 4 文字で書く。W < 4 ならラベルなし
+pub fn render_bar_labeled<const W: usize>(percent: u8, buf: &mut [u8; W]) -> &str {
+    let percent = percent.min(100);
+    render_bar(percent, buf);
+    if W >= 4 {
+        let start = (W - 4) / 2;
+        let label = [
+            if percent >= 100 { b'1' } else { b' ' },
+            if percent >= 10 {
+                b'0' + (percent / 10) % 10
+            } else {
+                b' '
+            },
+            b'0' + percent % 10,
+            b'%',
+        ];
+        buf[start..start + 4].copy_from_slice(&label);
+    }
+    // 安全：ASCIIのみを書き込む
+    unsafe { str::from_utf8_unchecked(&buf[..]) }
+}
+
+/// 符号付きの値を中央原点のバーで描画（例: `....<===|.......` / `.......|===>....`）
+/// 負は左、正は右へ伸びる。|value| >= max は端まで埋める（充電/放電の表示向け）
+pub fn render_bar_signed<const W: usize>(value: f32, max: f32, buf: &mut [u8; W]) -> &str {
+    if W == 0 {
+        return "";
+    }
+    buf.fill(b'.');
+    let center = W / 2;
+    buf[center] = b'|';
+
+    if value.is_finite() && max > 0.0 && value != 0.0 {
+        let ratio = (value.abs() / max).min(1.0);
+        if value < 0.0 {
+            let n = (ratio * center as f32) as usize;
+            if n > 0 {
+                buf[(center - n)..center].fill(b'=');
+                buf[center - n] = b'<';
+            }
+        } else {
+            let half = W - center - 1;
+            let n = (ratio * half as f32) as usize;
+            if n > 0 {
+                buf[(center + 1)..=(center + n)].fill(b'=');
+                buf[center + n] = b'>';
+            }
+        }
+    }
+    // 安全：ASCIIのみを書き込む
+    unsafe { str::from_utf8_unchecked(&buf[..]) }
+}
+
+/// ブロック文字 1 文字の UTF-8 バイト数（U+2580 台はすべて 3 バイト）
+pub const BLOCK_BYTES: usize = 3;
+
+/// 左詰め 1/8 ブロック（添字 = 埋まっている 1/8 の数、0 は空白）
+/// 横向きのバーなので下詰め（▁▂▃…）ではなく左詰めのグリフで端数を表す
+const LEFT_EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+/// ブロック文字（█ と 1/8 刻みの端数グリフ）でバーを生成。ASCII 版の 8 倍の分解能
+/// 列数は `buf.len() / BLOCK_BYTES`。未充填の列は空白（1 バイト）
+pub fn render_bar_unicode(percent: u8, buf: &mut [u8]) -> &str {
+    let cols = buf.len() / BLOCK_BYTES;
+    let eighths = (percent.min(100) as usize) * cols * 8 / 100;
+    let full = eighths / 8;
+    let rem = eighths % 8;
+
+    let mut len = 0;
+    for col in 0..cols {
+        let c = if col < full {
+            '█'
+        } else if col == full {
+            LEFT_EIGHTHS[rem]
+        } else {
+            ' '
+        };
+        len += c.encode_utf8(&mut buf[len..]).len();
+    }
+    // マルチバイト文字を含むため検査付きで変換
+    str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// スパークライン用の下詰め 1/8 ブロック（低 → 高）
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// 直近 N サンプルのスパークライン（1サンプル1グリフ、自身の min/max で自動スケール）
+#[derive(Clone, Copy)]
+pub struct Sparkline<const N: usize> {
+    history: SampleHistory<f32, N>,
+}
+
+impl<const N: usize> Sparkline<N> {
+    /// 新規作成
+    pub const fn new() -> Self {
+        Self {
+            history: SampleHistory::filled(0.0),
+        }
+    }
+
+    /// サンプルを追加（満杯時は最古を上書き、NaN/∞ は無視）
+    pub fn push(&mut self, x: f32) {
+        if !x.is_finite() {
+            return;
+        }
+        self.history.push(x);
+    }
+
+    /// 古い順にグリフを並べた文字列を生成。バッファに収まらない分は古い側から省く
+    /// 全サンプルが同値なら中段（▄）の平坦な線
+    pub fn render<'a>(&self, out: &'a mut [u8]) -> &'a str {
+        let fit = (out.len() / BLOCK_BYTES).min(self.history.len());
+        let skip = self.history.len() - fit;
+        let shown = || self.history.iter_recent().skip(skip);
+
+        let mut lo = f32::INFINITY;
+        let mut hi = f32::NEG_INFINITY;
+        for x in shown() {
+            if x < lo {
+                lo = x;
+            }
+            if x > hi {
+                hi = x;
+            }
+        }
+        let span = hi - lo;
+
+        let mut len = 0;
+        for x in shown() {
+            let level = if span > 0.0 {
+                let r = (x - lo) / span * (SPARK_LEVELS.len() - 1) as f32;
+                (libm::roundf(r) as usize).min(SPARK_LEVELS.len() - 1)
+            } else {
+                3
+            };
+            len += SPARK_LEVELS[level].encode_utf8(&mut out[len..]).len();
+        }
+        str::from_utf8(&out[..len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> Default for Sparkline<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// 整形1行の保持構造体（必要なら使用）。
 /// defmt では任意整形を制御しづらいため、本サンプルでは
 /// 直接 `render_bar` + 数値で出力する方針を採用。
@@ -43,9 +296,168 @@ pub struct TermLine<'a> {
 }
 
 /// 単位と値を揃えて1行整形の素材を作る（ラベル/値/単位/バー/％）
-pub fn line<'a>(label: &'a str, value: f32, unit: &'a str, max: f32, buf: &'a mut [u8; BAR_W]) -> TermLine<'a> {
+pub fn line<'a, const W: usize>(
+    label: &'a str,
+    value: f32,
+    unit: &'a str,
+    max: f32,
+    buf: &'a mut [u8; W],
+) -> TermLine<'a> {
     let percent = pct(value, max);
     let bar = render_bar(percent, buf);
-    TermLine { label, value, unit, percent, bar }
+    TermLine {
+        label,
+        value,
+        unit,
+        percent,
+        bar,
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_bar_fill_scales_with_width() {
+        let mut b10 = [0u8; 10];
+        let mut b40 = [0u8; 40];
+        let s10 = render_bar(50, &mut b10);
+        assert_eq!(s10, "====>.....");
+        let s40 = render_bar(50, &mut b40);
+        assert_eq!(s40.len(), 40);
+        assert_eq!(s40.bytes().filter(|&b| b == b'=').count(), 19);
+        assert_eq!(s40.find('>'), Some(19));
+        // 0% でも先頭に矢印、100% は末尾1文字を残して埋まる
+        assert_eq!(render_bar(0, &mut b10), ">.........");
+        assert_eq!(render_bar(255, &mut b10), "=========>");
+    }
+
+    #[test]
+    fn render_bar_unicode_ends_with_partial_block() {
+        // 10 列、55% → 44/8 列 = 全ブロック 5 + 4/8（▌）+ 空白 4
+        let mut buf = [0u8; 10 * BLOCK_BYTES];
+        let s = render_bar_unicode(55, &mut buf);
+        assert_eq!(s.len(), 6 * BLOCK_BYTES + 4);
+        assert_eq!(s.trim_end().chars().last(), Some('▌'));
+        assert_eq!(s.chars().filter(|&c| c == '█').count(), 5);
+        let s = render_bar_unicode(100, &mut buf);
+        assert_eq!(s, "██████████");
+    }
+
+    #[test]
+    fn render_bar_signed_negative_zero_positive() {
+        let mut buf = [0u8; 11];
+        assert_eq!(render_bar_signed(-50.0, 100.0, &mut buf), "...<=|.....");
+        assert_eq!(render_bar_signed(0.0, 100.0, &mut buf), ".....|.....");
+        assert_eq!(render_bar_signed(60.0, 100.0, &mut buf), ".....|==>..");
+        // |value| が max を超えたら端まで
+        assert_eq!(render_bar_signed(250.0, 100.0, &mut buf), ".....|====>");
+        assert_eq!(render_bar_signed(-250.0, 100.0, &mut buf), "<====|.....");
+    }
+
+    #[test]
+    fn pct_log_spaces_geometric_sequence_evenly() {
+        let ps: [u8; 5] =
+            core::array::from_fn(|k| pct_log(libm::powf(10.0, k as f32), 1.0, 10_000.0));
+        assert_eq!(ps[0], 0);
+        assert_eq!(ps[4], 100);
+        for w in ps.windows(2) {
+            let step = w[1] - w[0];
+            assert!((24..=26).contains(&step), "{ps:?}");
+        }
+        assert_eq!(pct_log(0.0, 1.0, 10_000.0), 0);
+        assert_eq!(pct_log(1e9, 1.0, 10_000.0), 100);
+    }
+
+    #[test]
+    fn sparkline_of_ramp_is_monotonic() {
+        let mut sp = Sparkline::<8>::new();
+        for k in 0..8 {
+            sp.push(k as f32);
+        }
+        let mut out = [0u8; 8 * BLOCK_BYTES];
+        let s = sp.render(&mut out);
+        assert_eq!(s, "▁▂▃▄▅▆▇█");
+        let glyphs: Vec<usize> = s
+            .chars()
+            .map(|c| SPARK_LEVELS.iter().position(|&g| g == c).unwrap())
+            .collect();
+        assert!(glyphs.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn sparkline_keeps_newest_when_buffer_is_short_and_flat_is_mid() {
+        let mut sp = Sparkline::<8>::new();
+        for k in 0..8 {
+            sp.push(k as f32);
+        }
+        let mut out = [0u8; 2 * BLOCK_BYTES];
+        assert_eq!(sp.render(&mut out), "▁█");
+        let mut flat = Sparkline::<4>::new();
+        for _ in 0..3 {
+            flat.push(1.0);
+        }
+        let mut out = [0u8; 4 * BLOCK_BYTES];
+        assert_eq!(flat.render(&mut out), "▄▄▄");
+    }
+
+    #[test]
+    fn render_bar_marked_below_and_above_fill() {
+        let mut buf = [0u8; 11];
+        assert_eq!(render_bar(50, &mut buf), "=====>.....");
+        assert_eq!(render_bar_marked(50, 20, &mut buf), "==!==>.....");
+        assert_eq!(render_bar_marked(50, 80, &mut buf), "=====>..!..");
+        assert_eq!(render_bar_marked(50, 200, &mut buf), "=====>....!");
+    }
+
+    #[test]
+    fn autoscale_max_relaxes_after_spike_with_decay() {
+        let mut held = AutoScale::new();
+        let mut decayed = AutoScale::with_decay(0.5);
+        assert_eq!((held.pct(100.0), decayed.pct(100.0)), (100, 100));
+        // スパイクの後は小さな値が続く
+        assert_eq!(held.pct(10.0), 10);
+        assert_eq!(decayed.pct(10.0), 20); // 基準 50
+        assert_eq!(decayed.pct(10.0), 40); // 基準 25
+        assert_eq!(held.max(), 100.0);
+        for _ in 0..10 {
+            decayed.pct(10.0);
+        }
+        // 減衰で基準が入力まで下がり、以降は入力が基準になる
+        assert_eq!(decayed.max(), 10.0);
+        assert_eq!(decayed.pct(10.0), 100);
+        // 範囲外の減衰係数は減衰なし
+        let mut bad = AutoScale::with_decay(1.5);
+        bad.pct(100.0);
+        bad.pct(1.0);
+        assert_eq!(bad.max(), 100.0);
+    }
+
+    #[test]
+    fn render_bar_labeled_places_percentage_at_center() {
+        let mut buf = [0u8; 20];
+        let s = render_bar_labeled(42, &mut buf);
+        assert_eq!(s.len(), 20);
+        // ラベル " 42%" は (20 - 4) / 2 = 8 列目から
+        assert_eq!(s.find("42%"), Some(9));
+        assert_eq!(&s[8..12], " 42%");
+        assert_eq!(&render_bar_labeled(100, &mut buf)[8..12], "100%");
+        assert_eq!(&render_bar_labeled(7, &mut buf)[8..12], "  7%");
+        let mut tiny = [0u8; 3];
+        assert_eq!(render_bar_labeled(42, &mut tiny), ">..");
+    }
+
+    #[test]
+    fn power_bar_maps_configured_max_to_full() {
+        // MAX_POWER_MW = 1000 のとき 500 mW は半分
+        assert_eq!(pct(500.0, 1_000.0), 50);
+        assert_eq!(pct(1_500.0, 1_000.0), 100);
+        assert_eq!(
+            (pct(-10.0, 1_000.0), pct(500.0, 0.0), pct(f32::NAN, 1_000.0)),
+            (0, 0, 0)
+        );
+        let mut buf = [0u8; 10];
+        assert_eq!(render_bar(pct(500.0, 1_000.0), &mut buf), "====>.....");
+    }
+}
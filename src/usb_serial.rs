@@ -0,0 +1,162 @@
+//! USB CDC ACM（仮想シリアル）出力（feature `usb-serial`）
+//! - プローブなしでも USB シリアル端末で1秒ごとの行を読めるようにする
+//! - ポーリングは USBCTRL_IRQ 割り込みで行う（メインループは WFI で眠るため）
+//! - ホスト未接続/端末未オープン/送信バッファ満杯のときは待たずに捨てる
+//! - 受信は1行単位のコマンド（`p250` で計測周期 250ms）。結果は `take_period_request()` で受け取る
+
+use core::cell::{Cell, RefCell};
+
+use cortex_m::interrupt::Mutex;
+use hal::clocks::UsbClock;
+use hal::pac::{self, interrupt};
+use rp2040_hal as hal;
+use usb_device::class_prelude::UsbBusAllocator;
+use usb_device::prelude::*;
+use usbd_serial::SerialPort;
+
+type Bus = hal::usb::UsbBus;
+
+static USB_DEVICE: Mutex<RefCell<Option<UsbDevice<'static, Bus>>>> = Mutex::new(RefCell::new(None));
+static USB_SERIAL: Mutex<RefCell<Option<SerialPort<'static, Bus>>>> =
+    Mutex::new(RefCell::new(None));
+static COMMAND: Mutex<RefCell<CommandBuf>> = Mutex::new(RefCell::new(CommandBuf::new()));
+static PERIOD_REQUEST: Mutex<Cell<Option<u32>>> = Mutex::new(Cell::new(None));
+
+/// USB デバイス（CDC ACM）を初期化して割り込みを有効化する。起動時に1回だけ呼ぶ
+pub fn init(
+    regs: pac::USBCTRL_REGS,
+    dpram: pac::USBCTRL_DPRAM,
+    usb_clock: UsbClock,
+    resets: &mut pac::RESETS,
+) {
+    let bus: &'static UsbBusAllocator<Bus> = match cortex_m::singleton!(
+        : UsbBusAllocator<Bus> = UsbBusAllocator::new(Bus::new(regs, dpram, usb_clock, true, resets))
+    ) {
+        Some(b) => b,
+        None => return, // 2回目以降の呼び出しは無視
+    };
+
+    let serial = SerialPort::new(bus);
+    let device = UsbDeviceBuilder::new(bus, UsbVidPid(0x16c0, 0x27dd))
+        .strings(&[StringDescriptors::default()
+            .manufacturer("pico-va-monitor")
+            .product("INA219 V/A monitor")
+            .serial_number("0001")])
+        .unwrap()
+        .device_class(usbd_serial::USB_CLASS_CDC)
+        .build();
+
+    cortex_m::interrupt::free(|cs| {
+        USB_SERIAL.borrow(cs).replace(Some(serial));
+        USB_DEVICE.borrow(cs).replace(Some(device));
+    });
+    // 安全：ハンドラ USBCTRL_IRQ は定義済みで、共有状態は Mutex 経由でのみ触る
+    unsafe { pac::NVIC::unmask(pac::Interrupt::USBCTRL_IRQ) };
+}
+
+/// 1行を送る（ブロックしない）。端末が開いていない/入りきらない分は捨てる
+pub fn write(bytes: &[u8]) {
+    cortex_m::interrupt::free(|cs| {
+        let mut dev = USB_DEVICE.borrow(cs).borrow_mut();
+        let mut serial = USB_SERIAL.borrow(cs).borrow_mut();
+        let (Some(dev), Some(serial)) = (dev.as_mut(), serial.as_mut()) else {
+            return;
+        };
+        if dev.state() != UsbDeviceState::Configured || !serial.dtr() {
+            return;
+        }
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            match serial.write(rest) {
+                Ok(n) if n > 0 => rest = &rest[n..],
+                _ => break,
+            }
+        }
+    });
+}
+
+/// 送信する1行の最大長（`ascii::Writer` に渡すバッファの大きさ）
+pub const LINE_MAX: usize = 160;
+
+/// 受信したコマンドで要求された計測周期 [ms]（未処理の最新1件、取り出すと空になる）
+pub fn take_period_request() -> Option<u32> {
+    cortex_m::interrupt::free(|cs| PERIOD_REQUEST.borrow(cs).take())
+}
+
+/// `p<数字>`（例: `p250`）を計測周期 [ms] として解釈。前後の空白は無視、範囲の丸めは呼び出し側
+pub fn parse_period_command(line: &[u8]) -> Option<u32> {
+    let line = line.trim_ascii();
+    let (&cmd, digits) = line.split_first()?;
+    if cmd != b'p' || digits.is_empty() {
+        return None;
+    }
+    let mut ms: u32 = 0;
+    for &b in digits {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        ms = ms.checked_mul(10)?.checked_add((b - b'0') as u32)?;
+    }
+    Some(ms)
+}
+
+/// 受信バイトを改行まで貯める1行バッファ（長すぎる行は捨てる）
+struct CommandBuf {
+    buf: [u8; 16],
+    len: usize,
+    overflow: bool,
+}
+
+impl CommandBuf {
+    const fn new() -> Self {
+        Self {
+            buf: [0; 16],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// 1バイト入力。改行で行が完結したら、その行を解釈した結果を返す
+    fn push(&mut self, b: u8) -> Option<u32> {
+        if b == b'\n' || b == b'\r' {
+            let cmd = if self.overflow {
+                None
+            } else {
+                parse_period_command(&self.buf[..self.len])
+            };
+            self.len = 0;
+            self.overflow = false;
+            return cmd;
+        }
+        if self.len < self.buf.len() {
+            self.buf[self.len] = b;
+            self.len += 1;
+        } else {
+            self.overflow = true;
+        }
+        None
+    }
+}
+
+/// USB 割り込み：列挙/送受信を進め、受信データをコマンドとして解釈する
+#[interrupt]
+fn USBCTRL_IRQ() {
+    cortex_m::interrupt::free(|cs| {
+        let mut dev = USB_DEVICE.borrow(cs).borrow_mut();
+        let mut serial = USB_SERIAL.borrow(cs).borrow_mut();
+        let (Some(dev), Some(serial)) = (dev.as_mut(), serial.as_mut()) else {
+            return;
+        };
+        if dev.poll(&mut [serial]) {
+            let mut rx = [0u8; 64];
+            if let Ok(n) = serial.read(&mut rx) {
+                let mut command = COMMAND.borrow(cs).borrow_mut();
+                for &b in &rx[..n] {
+                    if let Some(ms) = command.push(b) {
+                        PERIOD_REQUEST.borrow(cs).set(Some(ms));
+                    }
+                }
+            }
+        }
+    });
+}